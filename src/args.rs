@@ -9,10 +9,14 @@ use std::env;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+use crate::{Format, FrameRate};
+
 pub enum Action {
     Interpret,
     Compile,
     Check,
+    Convert,
+    Format,
 }
 
 impl FromStr for Action {
@@ -23,6 +27,8 @@ impl FromStr for Action {
             "interpret" | "i" => Ok(Action::Interpret),
             "compile" | "c" => Ok(Action::Compile),
             "verify" | "check" | "v" => Ok(Action::Check),
+            "convert" | "conv" => Ok(Action::Convert),
+            "fmt" | "format" => Ok(Action::Format),
             _ => Err("Not a valid action".into()),
         }
     }
@@ -31,24 +37,89 @@ impl FromStr for Action {
 pub struct Config {
     pub act: Action,
     pub infile: PathBuf,
+    pub outfile: Option<PathBuf>,
     pub dbg: bool,
+    pub record: Option<PathBuf>,
+    pub fps: Option<FrameRate>,
+    /// Whether to color diagnostics; `None` means only when stderr is a terminal.
+    pub color: Option<bool>,
+    /// The format `convert` writes.
+    pub to: Option<Format>,
+    /// Have `fmt` only report whether INPUT is formatted instead of rewriting it.
+    pub check: bool,
+    /// Whether unknown characters are errors; `None` means only for `verify`.
+    pub strict: Option<bool>,
 }
 
 impl Config {
     pub fn get() -> Result<Self, String> {
         let args = env::args();
         let mut args = args.skip(1);
-        let cfg = Config {
+        let mut cfg = Config {
             dbg: false,
+            outfile: None,
+            record: None,
+            fps: None,
+            color: None,
+            to: None,
+            check: false,
+            strict: None,
             act: Action::from_str(&args.next().ok_or("Not enough arguments.".to_owned())?)?,
             infile: PathBuf::from_str(&args.next().ok_or("Not enough arguments.".to_owned())?)
                 .unwrap(),
         };
-        if let Some(d) = args.next() {
-            if d == "--debug" || d == "-d" {
-                cfg.dbg = true;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--debug" | "-d" => cfg.dbg = true,
+                "--check" => cfg.check = true,
+                "--strict" => cfg.strict = Some(true),
+                "--no-strict" => cfg.strict = Some(false),
+                "--output" | "-o" => {
+                    let p = args
+                        .next()
+                        .ok_or("Expected a file after `--output`.".to_owned())?;
+                    cfg.outfile = Some(PathBuf::from_str(&p).unwrap());
+                }
+                "--record" | "-r" => {
+                    let p = args
+                        .next()
+                        .ok_or("Expected a file after `--record`.".to_owned())?;
+                    cfg.record = Some(PathBuf::from_str(&p).unwrap());
+                }
+                "--fps" | "-f" => {
+                    let f = args
+                        .next()
+                        .ok_or("Expected a frame rate after `--fps`.".to_owned())?;
+                    cfg.fps = Some(FrameRate::from_str(&f)?);
+                }
+                "--to" | "-t" => {
+                    let f = args
+                        .next()
+                        .ok_or("Expected a format after `--to`.".to_owned())?;
+                    cfg.to = Some(Format::from_str(&f)?);
+                }
+                a if a == "--color" || a.starts_with("--color=") => {
+                    let when = match a.split_once('=') {
+                        Some((_, w)) => w.to_owned(),
+                        None => args
+                            .next()
+                            .ok_or("Expected a value after `--color`.".to_owned())?,
+                    };
+                    cfg.color = match when.as_str() {
+                        "always" => Some(true),
+                        "never" => Some(false),
+                        "auto" => None,
+                        _ => {
+                            return Err(format!("`{}` is not one of auto, always or never.", when))
+                        }
+                    };
+                }
+                _ => return Err(format!("Unknown option `{}`.", arg)),
             }
         }
+        if let (Action::Convert, None) = (&cfg.act, cfg.to) {
+            return Err("`convert` needs a format given with `--to`.".into());
+        }
         Ok(cfg)
     }
 }
@@ -0,0 +1,40 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// A set of controller buttons.
+    ///
+    /// The bit layout matches xinput's, so a set can be handed to an xbox 360 style controller
+    /// directly once ZL and ZR are taken out.
+    #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+    pub struct Buttons: u16 {
+        const DUP = 0x1;
+        const DDOWN = 0x2;
+        const DLEFT = 0x4;
+        const DRIGHT = 0x8;
+        const MINUS = 0x10;
+        const PLUS = 0x20;
+        const LSTICK = 0x40;
+        const RSTICK = 0x80;
+        const L = 0x100;
+        const R = 0x200;
+        // switch has no GUIDE button so skip that value, and use it and the next for ZL and ZR
+        const ZL = 0x400;
+        const ZR = 0x800;
+        const A = 0x1000;
+        const B = 0x2000;
+        const X = 0x4000;
+        const Y = 0x8000;
+    }
+}
+
+impl Buttons {
+    /// The triggers, which are analog axes rather than buttons on an xbox 360 controller.
+    pub const TRIGGERS: Buttons = Buttons::ZL.union(Buttons::ZR);
+}
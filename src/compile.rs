@@ -0,0 +1,309 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+//! Packed binary input files (`.tasb`).
+//!
+//! All integers are little endian. The file starts with a 36 byte header:
+//!
+//! | offset | size | field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 4    | magic, `TASB`                          |
+//! | 4      | 2    | format version, currently 1            |
+//! | 6      | 1    | controller type, 0 = pro controller    |
+//! | 7      | 1    | reserved, 0                            |
+//! | 8      | 8    | frame rate numerator                   |
+//! | 16     | 8    | frame rate denominator                 |
+//! | 24     | 8    | total number of frames                 |
+//! | 32     | 4    | number of runs                         |
+//!
+//! followed by that many 16 byte runs, each holding one controller state and how many
+//! consecutive frames it lasts:
+//!
+//! | offset | size | field                     |
+//! |--------|------|---------------------------|
+//! | 0      | 4    | run length in frames      |
+//! | 4      | 2    | buttons                   |
+//! | 6      | 8    | lx, ly, rx, ry            |
+//! | 14     | 2    | left and right trigger    |
+
+use crate::parse::Tas;
+use crate::play::play;
+use crate::sink::{Controller, Gamepad, InputSink};
+use crate::{Buttons, FrameRate, TasError};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"TASB";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 36;
+const RUN_LEN: usize = 16;
+
+/// A tas that has already been turned into per-frame controller states.
+pub struct Compiled {
+    pub framerate: FrameRate,
+    pub controller: Controller,
+    pub frames: u64,
+    pub runs: Vec<(u32, Gamepad)>,
+}
+
+fn fs_err(e: std::io::Error) -> TasError {
+    TasError::Fs {
+        e: format!("{}", e),
+    }
+}
+
+fn bad_file(p: &Path, e: &str) -> TasError {
+    TasError::Fs {
+        e: format!("{}: {}", p.display(), e),
+    }
+}
+
+impl Compiled {
+    pub fn new(tas: &Tas) -> Self {
+        let mut runs: Vec<(u32, Gamepad)> = vec![];
+        let mut pad = Gamepad::default();
+        let mut frame = 0;
+        for (f, next) in tas.states() {
+            if f > frame {
+                push_run(&mut runs, f - frame, pad);
+            }
+            frame = f;
+            pad = next;
+        }
+        // the state set by the last line still needs its frame
+        push_run(&mut runs, 1, pad);
+        Compiled {
+            framerate: tas.meta.framerate,
+            controller: tas.meta.controller,
+            frames: frame + 1,
+            runs,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), TasError> {
+        let mut out = BufWriter::new(File::create(path).map_err(fs_err)?);
+        let mut buf = Vec::with_capacity(HEADER_LEN + RUN_LEN * self.runs.len());
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.push(self.controller as u8);
+        buf.push(0);
+        buf.extend_from_slice(&self.framerate.num().to_le_bytes());
+        buf.extend_from_slice(&self.framerate.den().to_le_bytes());
+        buf.extend_from_slice(&self.frames.to_le_bytes());
+        let count = u32::try_from(self.runs.len())
+            .map_err(|_| bad_file(path, "too many runs to fit in a compiled tas"))?;
+        buf.extend_from_slice(&count.to_le_bytes());
+        for (len, pad) in &self.runs {
+            buf.extend_from_slice(&len.to_le_bytes());
+            buf.extend_from_slice(&pad.buttons.bits().to_le_bytes());
+            for axis in [pad.lx, pad.ly, pad.rx, pad.ry].iter() {
+                buf.extend_from_slice(&axis.to_le_bytes());
+            }
+            buf.push(pad.lt);
+            buf.push(pad.rt);
+        }
+        out.write_all(&buf).map_err(fs_err)?;
+        out.flush().map_err(fs_err)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, TasError> {
+        let mut buf = vec![];
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut buf))
+            .map_err(fs_err)?;
+        if buf.len() < HEADER_LEN || &buf[0..4] != MAGIC {
+            return Err(bad_file(path, "not a compiled tas"));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let u32_at = |i: usize| {
+            let mut b = [0; 4];
+            b.copy_from_slice(&buf[i..i + 4]);
+            u32::from_le_bytes(b)
+        };
+        let u64_at = |i: usize| {
+            let mut b = [0; 8];
+            b.copy_from_slice(&buf[i..i + 8]);
+            u64::from_le_bytes(b)
+        };
+        if u16_at(4) != VERSION {
+            return Err(bad_file(path, "unsupported compiled tas version"));
+        }
+        let controller = match buf[6] {
+            0 => Controller::Pro,
+            _ => return Err(bad_file(path, "unknown controller type")),
+        };
+        let framerate = FrameRate::new(u64_at(8), u64_at(16))
+            .ok_or_else(|| bad_file(path, "invalid frame rate"))?;
+        let frames = u64_at(24);
+        let count = u32_at(32) as usize;
+        if buf.len() != HEADER_LEN + RUN_LEN * count {
+            return Err(bad_file(path, "truncated compiled tas"));
+        }
+        let runs: Vec<(u32, Gamepad)> = (0..count)
+            .map(|n| {
+                let i = HEADER_LEN + RUN_LEN * n;
+                let pad = Gamepad {
+                    buttons: Buttons::from_bits_retain(u16_at(i + 4)),
+                    lx: u16_at(i + 6) as i16,
+                    ly: u16_at(i + 8) as i16,
+                    rx: u16_at(i + 10) as i16,
+                    ry: u16_at(i + 12) as i16,
+                    lt: buf[i + 14],
+                    rt: buf[i + 15],
+                };
+                (u32_at(i), pad)
+            })
+            .collect();
+        if runs.iter().map(|(n, _)| u128::from(*n)).sum::<u128>() != u128::from(frames) {
+            return Err(bad_file(
+                path,
+                "run lengths don't add up to the frame count",
+            ));
+        }
+        Ok(Compiled {
+            framerate,
+            controller,
+            frames,
+            runs,
+        })
+    }
+
+    /// Whether `path` looks like a compiled tas rather than a script.
+    pub fn sniff(path: &Path) -> bool {
+        let mut magic = [0; 4];
+        File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .map(|_| &magic == MAGIC)
+            .unwrap_or(false)
+    }
+
+    pub fn run_tas(&self, sink: &mut dyn InputSink, dbg: bool) -> Result<(), TasError> {
+        let mut frame = 0;
+        let mut states = Vec::with_capacity(self.runs.len());
+        for (len, pad) in &self.runs {
+            states.push((frame, *pad));
+            frame += *len as u64;
+        }
+        play(&states, self.framerate, sink, dbg)
+    }
+}
+
+/// Add `len` frames of `pad`, split over as many runs as it takes to fit their lengths in a u32.
+fn push_run(runs: &mut Vec<(u32, Gamepad)>, mut len: u64, pad: Gamepad) {
+    if let Some((n, _)) = runs.last_mut().filter(|(_, last)| *last == pad) {
+        let room = u64::from(u32::MAX - *n).min(len);
+        *n += room as u32;
+        len -= room;
+    }
+    while len > 0 {
+        let n = len.min(u64::from(u32::MAX));
+        runs.push((n as u32, pad));
+        len -= n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collects what's played into it, without waiting between frames.
+    struct Collect(Vec<(u64, Gamepad)>);
+
+    impl InputSink for Collect {
+        fn connect(&mut self) -> Result<(), TasError> {
+            Ok(())
+        }
+
+        fn push(&mut self, frame: u64, pad: &Gamepad) -> Result<(), TasError> {
+            self.0.push((frame, *pad));
+            Ok(())
+        }
+
+        fn disconnect(&mut self) -> Result<(), TasError> {
+            Ok(())
+        }
+
+        fn realtime(&self) -> bool {
+            false
+        }
+    }
+
+    /// The state on each of the first `frames` frames.
+    fn per_frame(states: &[(u64, Gamepad)], frames: u64) -> Vec<Gamepad> {
+        let mut pad = Gamepad::default();
+        let mut next = states.iter().peekable();
+        (0..frames)
+            .map(|f| {
+                while let Some((_, p)) = next.next_if(|(at, _)| *at == f) {
+                    pad = *p;
+                }
+                pad
+            })
+            .collect()
+    }
+
+    fn temp(test: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tasc-{}-{}.tasb", test, std::process::id()))
+    }
+
+    const SCRIPT: &str =
+        "FRAMERATE 30\n1 ON{KEY_A,KEY_ZL} LSTICK{90,32767}\n4 OFF{KEY_A}\n9 RSTICK{x=-5,y=7}";
+
+    #[test]
+    fn round_trip() {
+        let tas: Tas = SCRIPT.parse().unwrap();
+        let path = temp("round-trip");
+        Compiled::new(&tas).write(&path).unwrap();
+        let loaded = Compiled::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.framerate, tas.meta.framerate);
+        assert_eq!(loaded.frames, 10);
+        let mut played = Collect(vec![]);
+        loaded.run_tas(&mut played, false).unwrap();
+        assert_eq!(per_frame(&played.0, 10), per_frame(&tas.states(), 10));
+    }
+
+    #[test]
+    fn long_gaps_are_split() {
+        let tas: Tas = "1 ON{KEY_A}\n5000000000 OFF{KEY_A}".parse().unwrap();
+        let c = Compiled::new(&tas);
+        assert_eq!(c.frames, 5_000_000_001);
+        let total: u64 = c.runs.iter().map(|(n, _)| u64::from(*n)).sum();
+        assert_eq!(total, c.frames);
+    }
+
+    #[test]
+    fn broken_files() {
+        let tas: Tas = SCRIPT.parse().unwrap();
+        let path = temp("broken");
+        Compiled::new(&tas).write(&path).unwrap();
+        let good = std::fs::read(&path).unwrap();
+        let load = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            match Compiled::load(&path) {
+                Err(TasError::Fs { e }) => e,
+                _ => panic!("loaded a broken file"),
+            }
+        };
+        let mut magic = good.clone();
+        magic[0] = b'X';
+        let mut version = good.clone();
+        version[4] = 2;
+        let truncated = &good[..good.len() - 1];
+        let mut frames = good.clone();
+        frames[24] += 1;
+        let errs = [load(&magic), load(&version), load(truncated), load(&frames)];
+        std::fs::remove_file(&path).unwrap();
+        assert!(errs[0].ends_with("not a compiled tas"));
+        assert!(errs[1].ends_with("unsupported compiled tas version"));
+        assert!(errs[2].ends_with("truncated compiled tas"));
+        assert!(errs[3].ends_with("run lengths don't add up to the frame count"));
+    }
+}
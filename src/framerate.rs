@@ -0,0 +1,100 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A frame rate stored as an exact fraction of frames per second, so that rates like
+/// 60000/1001 don't pick up rounding error over a long run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameRate {
+    num: u64,
+    den: u64,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl FrameRate {
+    /// `num / den` frames per second, or `None` if either is zero.
+    pub fn new(num: u64, den: u64) -> Option<Self> {
+        if num == 0 || den == 0 {
+            return None;
+        }
+        let g = gcd(num, den);
+        Some(FrameRate {
+            num: num / g,
+            den: den / g,
+        })
+    }
+
+    pub fn num(&self) -> u64 {
+        self.num
+    }
+
+    pub fn den(&self) -> u64 {
+        self.den
+    }
+
+    /// Time from the start of the script until `frame`, or the longest `Duration` can hold if
+    /// that's too far off to count in nanoseconds.
+    pub fn at(&self, frame: u64) -> Duration {
+        (frame as u128 * self.den as u128)
+            .checked_mul(1_000_000_000)
+            .and_then(|n| u64::try_from(n / self.num as u128).ok())
+            .map_or(Duration::MAX, Duration::from_nanos)
+    }
+}
+
+impl Default for FrameRate {
+    fn default() -> Self {
+        FrameRate { num: 60, den: 1 }
+    }
+}
+
+impl FromStr for FrameRate {
+    type Err = String;
+    /// Accepts whole numbers (`30`), decimals (`59.94`) and fractions (`60000/1001`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("`{}` is not a valid frame rate.", s);
+        let (num, den) = if let Some((n, d)) = s.split_once('/') {
+            (
+                n.trim().parse().map_err(|_| err())?,
+                d.trim().parse().map_err(|_| err())?,
+            )
+        } else if let Some((i, f)) = s.split_once('.') {
+            if f.is_empty() || f.len() > 9 || !f.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(err());
+            }
+            let den = 10u64.pow(f.len() as u32);
+            let i: u64 = i.parse().map_err(|_| err())?;
+            let f: u64 = f.parse().map_err(|_| err())?;
+            let num = i.checked_mul(den).and_then(|n| n.checked_add(f));
+            (num.ok_or_else(err)?, den)
+        } else {
+            (s.trim().parse().map_err(|_| err())?, 1)
+        };
+        FrameRate::new(num, den).ok_or_else(err)
+    }
+}
+
+impl Display for FrameRate {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
@@ -5,26 +5,72 @@
  * Refer to included LICENSE file.
  */
 
+//! An interpreter for tas-script.
+//!
+//! Besides running scripts through [`run_tas`] the way the `tasc` binary does, scripts can be
+//! parsed into a [`Tas`] and inspected line by line:
+//!
+//! ```
+//! use tasc::{Buttons, Tas};
+//!
+//! let tas: Tas = "1 ON{KEY_A} LSTICK{90,32767}\n5 OFF{KEY_A}".parse().unwrap();
+//! for line in &tas {
+//!     if line.on.contains(Buttons::A) {
+//!         println!("A pressed on frame {}", line.frame);
+//!     }
+//! }
+//! ```
+//!
+//! A parsed [`Tas`] can be played back into anything implementing [`InputSink`] with
+//! [`Tas::run_tas`].
+
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 
 mod args;
 pub use args::*;
+mod buttons;
+pub use buttons::Buttons;
+mod compile;
+use compile::Compiled;
+mod framerate;
+pub use framerate::FrameRate;
+mod meta;
+pub use meta::TasMetadata;
+mod nxtas;
 mod parse;
+pub use parse::{Format, Line, SourceFile, Stick, Tas};
+mod play;
+mod record;
+mod sched;
+mod script;
+mod sink;
+pub use sink::{Controller, Gamepad, InputSink};
+#[cfg(target_os = "linux")]
+mod uinput;
+#[cfg(windows)]
 mod vigem;
 
+/// `Parse` and `Syntax` errors point at columns `c..end` of line `l`, and keep a copy of that
+/// line in `src` so they can be shown with the offending source underlined.
 #[derive(Debug)]
 pub enum TasError {
     Parse {
         l: usize,
         c: usize,
+        end: usize,
         e: &'static str,
+        help: Option<String>,
+        src: String,
         p: PathBuf,
     },
     Syntax {
         l: usize,
         c: usize,
+        end: usize,
         e: &'static str,
+        help: Option<String>,
+        src: String,
         p: PathBuf,
     },
     Fs {
@@ -33,36 +79,206 @@ pub enum TasError {
     Vigem {
         e: String,
     },
+    Backend {
+        e: String,
+    },
 }
 
-impl Display for TasError {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
-        let rep = match self {
-            TasError::Parse { l, c, e, p } => format!(
-                "\x1b[31;1mParse Error:\x1b[0m \x1b[1m{}\x1b[0m\n\t->{}:{}:{}",
-                e,
-                p.display(),
+impl TasError {
+    /// Attach a note on how to fix the error; does nothing for errors without a location.
+    pub fn with_help(mut self, h: impl Into<String>) -> Self {
+        if let TasError::Parse { help, .. } | TasError::Syntax { help, .. } = &mut self {
+            *help = Some(h.into());
+        }
+        self
+    }
+
+    /// Format the error for a terminal, using ANSI colors if `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        let paint = |code: &str, s: &str| {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", code, s)
+            } else {
+                s.to_owned()
+            }
+        };
+        let (kind, l, c, end, e, help, src, p) = match self {
+            TasError::Parse {
                 l,
-                c
-            ),
-            TasError::Syntax { l, c, e, p } => format!(
-                "\x1b[31;1mSyntax Error:\x1b[0m \x1b[1m{}\x1b[0m\n\t->{}:{}:{}",
+                c,
+                end,
                 e,
-                p.display(),
+                help,
+                src,
+                p,
+            } => ("Parse Error:", l, c, end, e, help, src, p),
+            TasError::Syntax {
                 l,
-                c
-            ),
-            TasError::Fs { e } => format!("{}", e),
-            TasError::Vigem { e } => format!("ViGEm Error: {}", e),
+                c,
+                end,
+                e,
+                help,
+                src,
+                p,
+            } => ("Syntax Error:", l, c, end, e, help, src, p),
+            TasError::Fs { e } => return e.to_string(),
+            TasError::Vigem { e } => return format!("ViGEm Error: {}", e),
+            TasError::Backend { e } => return format!("Output Error: {}", e),
         };
-        write!(f, "{}", rep)
+        let gutter = " ".repeat(l.to_string().len());
+        let bar = paint("34;1", "|");
+        let mut rep = format!(
+            "{} {}\n{}{} {}:{}:{}\n{} {}\n{} {} {}\n{} {} {}{}",
+            paint("31;1", kind),
+            paint("1", e),
+            gutter,
+            paint("34;1", "-->"),
+            p.display(),
+            l,
+            c,
+            gutter,
+            bar,
+            paint("34;1", &l.to_string()),
+            bar,
+            src.replace('\t', " "),
+            gutter,
+            bar,
+            " ".repeat(*c),
+            paint("31;1", &"^".repeat((end.saturating_sub(*c)).max(1))),
+        );
+        if let Some(h) = help {
+            rep += &format!(
+                "\n{} {} {} {}",
+                gutter,
+                paint("34;1", "="),
+                paint("1", "help:"),
+                h
+            );
+        }
+        rep
+    }
+}
+
+impl Display for TasError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.render(false))
     }
 }
 
-pub fn run_tas(cfg: Config) -> Result<(), TasError> {
+impl From<TasError> for Vec<TasError> {
+    fn from(e: TasError) -> Self {
+        vec![e]
+    }
+}
+
+fn parse_tas(cfg: &Config) -> Result<Tas, Vec<TasError>> {
     let start = std::time::Instant::now();
-    let tas = parse::gen_tas(cfg.infile)?;
+    let mut src = SourceFile::read(cfg.infile.clone())?;
+    src.strict = cfg.strict.unwrap_or(matches!(cfg.act, Action::Check));
+    let mut tas = src.parse()?;
+    if let Some(fps) = cfg.fps {
+        tas.meta.framerate = fps;
+    }
     println!("Parsed tas in {}ms", start.elapsed().as_millis());
-    tas.run_tas(cfg.dbg);
-    Ok(())
+    Ok(tas)
+}
+
+pub fn run_tas(cfg: Config) -> Result<(), Vec<TasError>> {
+    if let Action::Check = cfg.act {
+        let tas = parse_tas(&cfg)?;
+        let frames = tas.lines.last().map_or(0, |l| l.frame + 1);
+        println!(
+            "{} is valid: {} lines over {} frames",
+            cfg.infile.display(),
+            tas.lines.len(),
+            frames
+        );
+        print!("{}", tas.meta);
+        return Ok(());
+    }
+    if let Action::Compile = cfg.act {
+        let tas = parse_tas(&cfg)?;
+        let out = match cfg.outfile {
+            Some(o) => o,
+            None => cfg.infile.with_extension("tasb"),
+        };
+        let compiled = Compiled::new(&tas);
+        compiled.write(&out)?;
+        println!("Compiled {} frames to {}", compiled.frames, out.display());
+        return Ok(());
+    }
+    if let Action::Convert = cfg.act {
+        let to = cfg.to.unwrap_or(Format::NxTas);
+        let tas = parse_tas(&cfg)?;
+        let text = match to {
+            Format::NxTas => {
+                let header = tas.meta.directives();
+                if !header.is_empty() {
+                    let names: Vec<&str> =
+                        header.iter().filter_map(|d| d.split(' ').next()).collect();
+                    println!(
+                        "nx-TAS has no header, so {} will be left out",
+                        names.join(", ")
+                    );
+                }
+                nxtas::write(&tas)
+            }
+            Format::TasScript => script::write(&tas),
+        };
+        let out = match cfg.outfile {
+            Some(o) => o,
+            None => cfg.infile.with_extension(to.extension()),
+        };
+        let same = |a: &PathBuf, b: &PathBuf| match (a.canonicalize(), b.canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => a == b,
+        };
+        if same(&out, &cfg.infile) {
+            return Err(TasError::Fs {
+                e: format!(
+                    "{}: converting would overwrite the input, pick another file with `-o`",
+                    out.display()
+                ),
+            }
+            .into());
+        }
+        std::fs::write(&out, text).map_err(|e| TasError::Fs {
+            e: format!("{}: {}", out.display(), e),
+        })?;
+        println!("Converted {} to {}", cfg.infile.display(), out.display());
+        return Ok(());
+    }
+    if let Action::Format = cfg.act {
+        let mut src = SourceFile::read(cfg.infile.clone())?;
+        src.strict = cfg.strict.unwrap_or(false);
+        let text = script::format(&src)?;
+        if cfg.check {
+            if text != src.text {
+                return Err(TasError::Fs {
+                    e: format!("{} is not formatted", cfg.infile.display()),
+                }
+                .into());
+            }
+            return Ok(());
+        }
+        let out = cfg.outfile.unwrap_or(cfg.infile);
+        std::fs::write(&out, text).map_err(|e| TasError::Fs {
+            e: format!("{}: {}", out.display(), e),
+        })?;
+        return Ok(());
+    }
+    let mut sink = match cfg.record {
+        Some(ref p) => Box::new(record::Recorder::new(p.clone())),
+        None => sink::default_sink()?,
+    };
+    if Compiled::sniff(&cfg.infile) {
+        let mut tas = Compiled::load(&cfg.infile)?;
+        if let Some(fps) = cfg.fps {
+            tas.framerate = fps;
+        }
+        return Ok(tas.run_tas(sink.as_mut(), cfg.dbg)?);
+    }
+    let tas = parse_tas(&cfg)?;
+    print!("{}", tas.meta);
+    Ok(tas.run_tas(sink.as_mut(), cfg.dbg)?)
 }
@@ -5,16 +5,39 @@
  * Refer to included LICENSE file.
  */
 
-use tasc::{run_tas, Config};
+use std::io::IsTerminal;
+use tasc::{run_tas, Action, Config, TasError};
 const USAGE: &str = "compile or interpret tas-script\n
 USAGE:
-    tasc SUBCOMMAND INPUT\n
+    tasc SUBCOMMAND INPUT [OPTIONS]\n
 INPUT:
-    The file to be compiled or interpreted.\n
+    The file to be compiled or interpreted. Files made by `compile` can be
+    interpreted just like scripts.\n
 SUBCOMMAND:
     interpret, i  interpret INPUT in real time
-    verify, v     check that INPUT uses valid syntax
-    compile, c    compile INPUT to an executable\n";
+    verify, v     check that INPUT is a valid script without running it,
+                  exiting with status 2 if it is not
+    compile, c    compile INPUT to a packed binary input file
+    convert       convert INPUT to the format given with --to
+    fmt           rewrite INPUT as canonical tas-script\n
+OPTIONS:
+    --check              have `fmt` exit with status 1 if INPUT isn't formatted
+                         instead of rewriting it
+    --color WHEN         color diagnostics: auto (the default, only when stderr
+                         is a terminal), always or never
+    -d, --debug          print every controller state as it is sent
+    -f, --fps RATE       play back at RATE frames per second, e.g. 30, 59.94 or
+                         60000/1001, overriding the script's FRAMERATE
+    -o, --output FILE    where `compile`, `convert` and `fmt` write to; INPUT
+                         itself for `fmt`, and INPUT with a .tasb, .tas.txt or
+                         .nxtas extension otherwise
+    -r, --record FILE    write the state of every frame to FILE as csv instead
+                         of sending it to a controller
+    --strict             treat characters that mean nothing in a script as
+                         errors rather than skipping them; on by default for
+                         `verify`, which takes --no-strict to turn it off
+    -t, --to FORMAT      the format `convert` writes: tas-script or nxtas (also
+                         read by yuzu's TAS player)\n";
 
 fn main() {
     let cfg = Config::get();
@@ -24,8 +47,19 @@ fn main() {
         std::process::exit(1);
     }
     let cfg = cfg.unwrap();
-    run_tas(cfg).unwrap_or_else(|e| {
-        eprintln!("{}", e);
+    let check = matches!(cfg.act, Action::Check);
+    let color = cfg.color.unwrap_or_else(|| std::io::stderr().is_terminal());
+    run_tas(cfg).unwrap_or_else(|errs| {
+        for e in &errs {
+            eprintln!("{}\n", e.render(color));
+        }
+        let invalid = errs
+            .iter()
+            .all(|e| matches!(e, TasError::Parse { .. } | TasError::Syntax { .. }));
+        if check && invalid {
+            eprintln!("{} errors found", errs.len());
+            std::process::exit(2)
+        }
         std::process::exit(1)
     })
 }
@@ -0,0 +1,92 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+use std::fmt::{Display, Formatter};
+
+use crate::{Controller, FrameRate};
+
+/// What a script is for, from the directives at its top. Only the frame rate and controller
+/// change how a script plays; the rest is there for people running it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TasMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub game: Option<String>,
+    pub game_version: Option<String>,
+    pub emulator: Option<String>,
+    /// Rate the frame numbers of the script are played back at.
+    pub framerate: FrameRate,
+    pub controller: Controller,
+    /// What has to be set up before playback, e.g. which save file to load.
+    pub start: Option<String>,
+}
+
+impl TasMetadata {
+    /// The field set by the header directive `directive`, for those that take any text.
+    pub(crate) fn text_mut(&mut self, directive: &str) -> Option<&mut Option<String>> {
+        match directive {
+            "TITLE" => Some(&mut self.title),
+            "AUTHOR" => Some(&mut self.author),
+            "GAME" => Some(&mut self.game),
+            "GAME_VERSION" => Some(&mut self.game_version),
+            "EMULATOR" => Some(&mut self.emulator),
+            "START" => Some(&mut self.start),
+            _ => None,
+        }
+    }
+
+    /// The header lines giving everything that isn't left at its default.
+    pub(crate) fn directives(&self) -> Vec<String> {
+        let framerate = Some(self.framerate).filter(|f| *f != FrameRate::default());
+        let controller = Some(self.controller).filter(|c| *c != Controller::default());
+        let fields = [
+            ("TITLE", self.title.clone()),
+            ("AUTHOR", self.author.clone()),
+            ("GAME", self.game.clone()),
+            ("GAME_VERSION", self.game_version.clone()),
+            ("EMULATOR", self.emulator.clone()),
+            ("FRAMERATE", framerate.map(|f| f.to_string())),
+            ("CONTROLLER", controller.map(|c| c.to_string())),
+            ("START", self.start.clone()),
+        ];
+        fields
+            .iter()
+            .filter_map(|(d, v)| {
+                let v = v.as_ref()?;
+                // quotes keep anything that looks like a comment in the value
+                if v.contains('#') || v.contains("//") || v.contains("/*") {
+                    Some(format!("{} \"{}\"", d, v))
+                } else {
+                    Some(format!("{} {}", d, v))
+                }
+            })
+            .collect()
+    }
+}
+
+impl Display for TasMetadata {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        let fields = [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Game", &self.game),
+            ("Game version", &self.game_version),
+            ("Emulator", &self.emulator),
+        ];
+        for (name, v) in fields.iter() {
+            if let Some(v) = v {
+                writeln!(f, "{}: {}", name, v)?;
+            }
+        }
+        writeln!(f, "Frame rate: {} fps", self.framerate)?;
+        writeln!(f, "Controller: {}", self.controller)?;
+        if let Some(s) = &self.start {
+            writeln!(f, "Start: {}", s)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+//! The nx-TAS line format used by TAS-nx and yuzu's built in TAS player.
+//!
+//! Every line gives the full controller state on one frame:
+//!
+//! ```text
+//! 1 KEY_A;KEY_ZR 0;32767 0;0
+//! 2 NONE 0;32767 0;0
+//! ```
+//!
+//! Frames that don't have a line have nothing held and both sticks centered.
+
+use std::fmt::Write;
+
+use crate::parse::{key_to_buttons, suggest, SourceFile, KEYS};
+use crate::{Buttons, Gamepad, Line, Stick, Tas, TasError, TasMetadata};
+
+/// The whitespace separated fields of `text`, each with the column it starts at.
+fn fields(text: &str) -> Vec<(usize, &str)> {
+    let mut out = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                out.push((s, &text[s..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        out.push((s, &text[s..]));
+    }
+    out
+}
+
+fn get_keys(src: &SourceFile, l: usize, c: usize, keys: &str) -> Result<Buttons, TasError> {
+    if keys == "NONE" {
+        return Ok(Buttons::empty());
+    }
+    let mut out = Buttons::empty();
+    let mut c = c;
+    for k in keys.split(';') {
+        match key_to_buttons(k) {
+            Some(b) if k.starts_with("KEY_") => out |= b,
+            _ => {
+                let err = src.parse_err((l, c, c + k.len()), "Unknown key identifier.");
+                return Err(suggest(err, k, &KEYS));
+            }
+        }
+        c += k.len() + 1;
+    }
+    Ok(out)
+}
+
+fn get_stick(src: &SourceFile, l: usize, c: usize, s: &str) -> Result<Stick, TasError> {
+    let err = || {
+        src.parse_err((l, c, c + s.len()), "Malformed stick information.")
+            .with_help("sticks are written as `x;y`, each between -32768 and 32767")
+    };
+    let (x, y) = s.split_once(';').ok_or_else(err)?;
+    match (x.parse(), y.parse()) {
+        (Ok(x), Ok(y)) => Ok(Stick::new(x, y)),
+        _ => Err(err()),
+    }
+}
+
+pub(crate) fn parse(src: &SourceFile) -> Result<Tas, Vec<TasError>> {
+    let mut lines = vec![];
+    let mut errs = vec![];
+    let mut last_frame: Option<u64> = None;
+    let (mut held, mut lstick, mut rstick) = (Buttons::empty(), Stick::default(), Stick::default());
+    for (n, text) in src.text.lines().enumerate() {
+        let l = n + 1;
+        let f = fields(text);
+        if f.is_empty() {
+            continue;
+        }
+        if f.len() != 4 {
+            errs.push(
+                src.parse_err((l, f[0].0, text.trim_end().len()), "Malformed nx-TAS line.")
+                    .with_help("lines are written as `frame keys lx;ly rx;ry`"),
+            );
+            continue;
+        }
+        let (c, num) = f[0];
+        let frame: u64 = match num.parse() {
+            Ok(u64::MAX) => {
+                errs.push(src.parse_err((l, c, c + num.len()), "This frame number is too large."));
+                continue;
+            }
+            Ok(n) => n,
+            Err(_) => {
+                errs.push(src.parse_err((l, c, c + num.len()), "Invalid frame number."));
+                continue;
+            }
+        };
+        if let Some(prev) = last_frame.filter(|p| frame <= *p) {
+            errs.push(
+                src.parse_err(
+                    (l, c, c + num.len()),
+                    "Frame numbers must strictly increase.",
+                )
+                .with_help(format!("the previous line is on frame {}", prev)),
+            );
+            continue;
+        }
+        let state = (
+            get_keys(src, l, f[1].0, f[1].1),
+            get_stick(src, l, f[2].0, f[2].1),
+            get_stick(src, l, f[3].0, f[3].1),
+        );
+        let (keys, ls, rs) = match state {
+            (Ok(k), Ok(ls), Ok(rs)) => (k, ls, rs),
+            (k, ls, rs) => {
+                errs.extend(k.err());
+                errs.extend(ls.err());
+                errs.extend(rs.err());
+                continue;
+            }
+        };
+        // anything still held going into a gap gets let go right after the last listed frame
+        if let Some(prev) = last_frame.filter(|p| frame > p + 1) {
+            let neutral = Stick::default();
+            if !held.is_empty() || lstick != neutral || rstick != neutral {
+                lines.push(Line {
+                    frame: prev + 1,
+                    on: Buttons::empty(),
+                    off: held,
+                    lstick: Some(neutral).filter(|_| lstick != neutral),
+                    rstick: Some(neutral).filter(|_| rstick != neutral),
+                });
+                held = Buttons::empty();
+                lstick = neutral;
+                rstick = neutral;
+            }
+        }
+        last_frame = Some(frame);
+        let line = Line {
+            frame,
+            on: keys - held,
+            off: held - keys,
+            lstick: Some(ls).filter(|s| *s != lstick),
+            rstick: Some(rs).filter(|s| *s != rstick),
+        };
+        held = keys;
+        lstick = ls;
+        rstick = rs;
+        if !line.on.is_empty()
+            || !line.off.is_empty()
+            || line.lstick.is_some()
+            || line.rstick.is_some()
+        {
+            lines.push(line);
+        }
+    }
+    if errs.is_empty() {
+        Ok(Tas {
+            meta: TasMetadata::default(),
+            lines,
+        })
+    } else {
+        Err(errs)
+    }
+}
+
+/// Write `tas` out as nx-TAS, with one line for every frame where anything is held or a stick is
+/// off center. The frame rate is lost, since the format has nowhere to put it.
+pub(crate) fn write(tas: &Tas) -> String {
+    let mut out = String::new();
+    let states = tas.states();
+    for (i, (frame, pad)) in states.iter().enumerate() {
+        if *pad == Gamepad::default() {
+            continue;
+        }
+        // the state lasts until the next line, or just its own frame for the last one
+        let until = states.get(i + 1).map_or(frame + 1, |(f, _)| *f);
+        let keys = if pad.buttons.is_empty() {
+            "NONE".to_owned()
+        } else {
+            let names: Vec<String> = pad
+                .buttons
+                .iter_names()
+                .map(|(n, _)| format!("KEY_{}", n))
+                .collect();
+            names.join(";")
+        };
+        for f in *frame..until {
+            writeln!(
+                out,
+                "{} {} {};{} {};{}",
+                f, keys, pad.lx, pad.ly, pad.rx, pad.ry
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Format;
+    use std::path::PathBuf;
+
+    fn nx(text: &str) -> Tas {
+        parse(&SourceFile::new(text.to_owned(), None)).unwrap()
+    }
+
+    fn format(text: &str, name: Option<&str>) -> Format {
+        SourceFile::new(text.to_owned(), name.map(PathBuf::from)).format()
+    }
+
+    #[test]
+    fn held_keys_only_change_once() {
+        let tas = nx("1 KEY_A 0;0 0;0\n2 KEY_A;KEY_B 0;0 0;0\n3 KEY_B 0;0 0;0\n");
+        let lines: Vec<(u64, Buttons, Buttons)> =
+            tas.lines.iter().map(|l| (l.frame, l.on, l.off)).collect();
+        assert_eq!(
+            lines,
+            vec![
+                (1, Buttons::A, Buttons::empty()),
+                (2, Buttons::B, Buttons::empty()),
+                (3, Buttons::empty(), Buttons::A),
+            ]
+        );
+    }
+
+    #[test]
+    fn gaps_release_everything() {
+        let tas = nx("1 KEY_A 0;32767 0;0\n5 KEY_B 0;0 0;0\n");
+        assert_eq!(tas.lines.len(), 3);
+        let gap = &tas.lines[1];
+        assert_eq!(
+            (gap.frame, gap.on, gap.off),
+            (2, Buttons::empty(), Buttons::A)
+        );
+        assert_eq!(gap.lstick, Some(Stick::default()));
+        assert_eq!(gap.rstick, None);
+        assert_eq!((tas.lines[2].frame, tas.lines[2].on), (5, Buttons::B));
+    }
+
+    #[test]
+    fn written_nxtas_parses_back() {
+        let tas: Tas =
+            "1 ON{KEY_A,KEY_ZL} LSTICK{90,32767}\n4 OFF{KEY_A}\n9 OFF{KEY_ZL} RSTICK{x=-5,y=7}"
+                .parse()
+                .unwrap();
+        let text = write(&tas);
+        assert_eq!(nx(&text).states(), tas.states());
+        assert_eq!(write(&nx(&text)), text);
+    }
+
+    #[test]
+    fn sniffing() {
+        assert_eq!(format("1 KEY_A 0;0 0;0\n", None), Format::NxTas);
+        assert_eq!(
+            format("# made in yuzu\n1 KEY_A 0;0 0;0\n", None),
+            Format::NxTas
+        );
+        assert_eq!(format("1 ON{KEY_A}\n", Some("run.nxtas")), Format::NxTas);
+        assert_eq!(format("1 ON{KEY_A}\n", None), Format::TasScript);
+        assert_eq!(format("# a;b\n1 ON{KEY_A}\n", None), Format::TasScript);
+        assert_eq!(format("TITLE a;b\n1 ON{KEY_A}\n", None), Format::TasScript);
+    }
+}
@@ -5,34 +5,969 @@
  * Refer to included LICENSE file.
  */
 
-use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
-use std::fs::read_to_string;
-use std::path::PathBuf;
+use std::fs::{canonicalize, read_to_string};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::slice::Iter;
-use std::time::Duration;
+use std::str::Chars;
+use std::str::FromStr;
 
-static PATH: OnceCell<PathBuf> = OnceCell::new();
+use crate::{nxtas, Buttons, TasError, TasMetadata};
 
-use crate::TasError;
+/// The script formats tasc can read.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// [tas-script](https://github.com/hamhub7/tas-script), tasc's own format.
+    TasScript,
+    /// The nx-TAS format used by TAS-nx and yuzu.
+    NxTas,
+}
+
+impl Format {
+    /// The extension `convert` gives files in this format. yuzu's scripts are `.txt` too, so
+    /// tas-script gets a longer one that can't collide with them.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::TasScript => "tas.txt",
+            Format::NxTas => "nxtas",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tas-script" | "tasscript" => Ok(Format::TasScript),
+            "nxtas" | "nx-tas" | "yuzu" => Ok(Format::NxTas),
+            _ => Err(format!("`{}` is not one of tas-script or nxtas.", s)),
+        }
+    }
+}
+
+/// The text of a script along with the name its errors are reported under.
+#[derive(Clone)]
+pub struct SourceFile {
+    pub name: PathBuf,
+    pub text: String,
+    /// Reject characters that mean nothing in tas-script instead of skipping over them.
+    pub strict: bool,
+}
+
+impl SourceFile {
+    /// `name` only shows up in diagnostics, so scripts that didn't come from a file can leave it out.
+    pub fn new(text: String, name: Option<PathBuf>) -> Self {
+        SourceFile {
+            name: name.unwrap_or_else(|| PathBuf::from("<input>")),
+            text,
+            strict: false,
+        }
+    }
+
+    pub fn read(path: PathBuf) -> Result<Self, TasError> {
+        let text = read_to_string(&path).map_err(|e| TasError::Fs {
+            e: format!("{}: {}", path.display(), e),
+        })?;
+        Ok(SourceFile::new(text, Some(path)))
+    }
+
+    pub(crate) fn line(&self, l: usize) -> String {
+        self.text.lines().nth(l - 1).unwrap_or("").to_owned()
+    }
 
-#[derive(Debug)]
+    pub(crate) fn parse_err(&self, (l, c, end): Pos, e: &'static str) -> TasError {
+        TasError::Parse {
+            l,
+            c,
+            end,
+            e,
+            help: None,
+            src: self.line(l),
+            p: self.name.clone(),
+        }
+    }
+
+    fn syntax_err(&self, (l, c, end): Pos, e: &'static str) -> TasError {
+        TasError::Syntax {
+            l,
+            c,
+            end,
+            e,
+            help: None,
+            src: self.line(l),
+            p: self.name.clone(),
+        }
+    }
+
+    /// Work out which format the file is written in, from its extension if it has a telling
+    /// one or else from what its first line looks like.
+    pub fn format(&self) -> Format {
+        if self.name.extension().is_some_and(|e| e == "nxtas") {
+            return Format::NxTas;
+        }
+        // tas-script frame lines never have a `;` outside comments, while the stick fields of an
+        // nx-TAS line always do. Header values can hold anything, so the line also has to start
+        // with a plain frame number.
+        let first = self
+            .text
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with(['#', '/']));
+        let frame = |l: &str| {
+            l.split(' ')
+                .next()
+                .is_some_and(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()))
+        };
+        match first {
+            Some(l) if frame(l) && !l.contains('{') && l.contains(';') => Format::NxTas,
+            _ => Format::TasScript,
+        }
+    }
+
+    /// Lex and parse the whole file, returning every error found sorted by position.
+    pub fn parse(&self) -> Result<Tas, Vec<TasError>> {
+        if let Format::NxTas = self.format() {
+            return nxtas::parse(self);
+        }
+        let (tok, mut errs) = lex(self);
+        match Tas::parse_tas(tok, self) {
+            Ok(tas) if errs.is_empty() => return Ok(tas),
+            Ok(_) => {}
+            Err(e) => errs.extend(e),
+        }
+        // this file's errors first, then those of each file it includes
+        errs.sort_by_key(|e| match e {
+            TasError::Parse { l, c, p, .. } | TasError::Syntax { l, c, p, .. } => {
+                (*p != self.name, p.clone(), *l, *c)
+            }
+            _ => (false, PathBuf::new(), 0, 0),
+        });
+        // a line in a macro body that's broken is broken for every call
+        errs.dedup_by(|a, b| a.to_string() == b.to_string());
+        Err(errs)
+    }
+}
+
+/// A parsed script.
+#[derive(Debug, PartialEq)]
 pub struct Tas {
+    /// The header: what the script is for, and how fast to play it.
+    pub meta: TasMetadata,
+    /// Every line of the script, in order of strictly increasing frame.
     pub lines: Vec<Line>,
 }
 
 impl Tas {
-    fn parse_tas(prog: Vec<Token>) -> Result<Self, TasError> {
-        let mut lines = vec![];
-        let prog_lines = prog.split(|t| matches!(t, Token::Newline(_)));
-        for line in prog_lines {
-            lines.push(Line::get(line)?);
+    /// Read and parse the script at `path`.
+    pub fn from_path(path: impl Into<PathBuf>) -> Result<Self, Vec<TasError>> {
+        SourceFile::read(path.into())?.parse()
+    }
+
+    /// Iterate over the lines of the script in frame order.
+    pub fn lines(&self) -> Iter<'_, Line> {
+        self.lines.iter()
+    }
+
+    /// Parse every line, collecting the errors of all lines that fail rather than
+    /// stopping at the first one.
+    fn parse_tas(prog: Vec<Token>, src: &SourceFile) -> Result<Self, Vec<TasError>> {
+        let mut parser = Parser::new(Rc::new(src.clone()));
+        parser.feed(prog);
+        parser.finish()
+    }
+}
+
+/// A `MACRO` definition: the names of its parameters and the lines of its body, still as tokens.
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Vec<Token>>,
+    /// The file the macro is defined in, which errors in its body are reported against.
+    src: Rc<SourceFile>,
+}
+
+impl Macro {
+    /// What each use of the parameter `param` in the body stands in for.
+    fn slots(&self, param: &str) -> Vec<Slot> {
+        let mut slots = vec![];
+        for line in &self.body {
+            let mut op = None;
+            for (i, t) in line.iter().enumerate() {
+                match t {
+                    Token::Operation(o, _) => op = Some(o.as_str()),
+                    Token::Call(_, _) => op = None,
+                    Token::Param(p, _) if p == param => slots.push(match op {
+                        _ if i == 0 => Slot::Frame,
+                        Some("ON" | "OFF" | "RAW") => Slot::Key,
+                        Some(_) => Slot::Stick,
+                        // an argument to another macro, which checks it itself
+                        None => continue,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        slots
+    }
+}
+
+/// The kind of token a parameter stands in for where it's used.
+#[derive(Clone, Copy)]
+enum Slot {
+    Frame,
+    Key,
+    Stick,
+}
+
+impl Slot {
+    fn name(self) -> &'static str {
+        match self {
+            Slot::Frame => "a frame number",
+            Slot::Key => "a key",
+            Slot::Stick => "a stick parameter",
+        }
+    }
+
+    fn fits(self, arg: &Token) -> Result<(), &'static str> {
+        match (self, arg) {
+            (Slot::Frame, Token::Number(n, _)) => n.frame().map(|_| ()),
+            (Slot::Frame, _) => Err("Expected a frame number for this argument."),
+            (Slot::Key, Token::Key(_, _)) => Ok(()),
+            (Slot::Key, _) => Err("Expected a key for this argument."),
+            (Slot::Stick, Token::Number(_, _)) => Ok(()),
+            (Slot::Stick, _) => Err("Expected a stick parameter for this argument."),
+        }
+    }
+}
+
+/// A macro whose body is still being read.
+struct Definition {
+    /// `None` if the `MACRO` line was broken, in which case the body is read and thrown away.
+    name: Option<String>,
+    pos: Pos,
+    mac: Macro,
+    /// How many `REPEAT` blocks deep into the body the next line is.
+    depth: usize,
+}
+
+/// Everything needed to parse a script one line at a time.
+struct Parser {
+    /// The file the current line is from: the script, or the one a macro was defined in.
+    src: Rc<SourceFile>,
+    meta: TasMetadata,
+    /// The header directives given so far, each of which can only be given once.
+    header: Vec<String>,
+    errs: Vec<TasError>,
+    seen_frame: bool,
+    /// The whole script, then every block around the current line from the outside in.
+    blocks: Vec<Block>,
+    macros: HashMap<String, Macro>,
+    defining: Option<Definition>,
+    /// The macros being expanded, innermost last.
+    calling: Vec<String>,
+    /// This file and the files that include it, innermost last, to catch include cycles.
+    includes: Vec<PathBuf>,
+}
+
+impl Parser {
+    fn new(src: Rc<SourceFile>) -> Self {
+        Parser {
+            includes: canonicalize(&src.name).into_iter().collect(),
+            src,
+            meta: TasMetadata::default(),
+            header: vec![],
+            errs: vec![],
+            seen_frame: false,
+            blocks: vec![Block::new(0, 1, (0, 0, 0))],
+            macros: HashMap::new(),
+            defining: None,
+            calling: vec![],
+        }
+    }
+
+    fn feed(&mut self, prog: Vec<Token>) {
+        let prog: Vec<Token> = prog
+            .into_iter()
+            .filter(|t| !matches!(t, Token::Comment(_, _)))
+            .collect();
+        let prog_lines = prog
+            .split(|t| matches!(t, Token::Newline(_)))
+            // whatever came before a comment at the start of the line
+            .map(|l| {
+                &l[l.iter()
+                    .take_while(|t| matches!(t, Token::Whitespace(_)))
+                    .count()..]
+            });
+        for line in prog_lines.filter(|l| !l.is_empty()) {
+            self.line(line);
+        }
+    }
+
+    fn line(&mut self, line: &[Token]) {
+        let src = Rc::clone(&self.src);
+        if let Some(def) = &mut self.defining {
+            match line[0] {
+                Token::BlockClose(_) if def.depth == 0 => {
+                    let def = self.defining.take().unwrap();
+                    if def.mac.body.is_empty() {
+                        self.errs
+                            .push(src.parse_err(def.pos, "This macro has no lines in it."));
+                    } else if let Some(name) = def.name {
+                        self.macros.insert(name, def.mac);
+                    }
+                    return;
+                }
+                Token::BlockClose(_) => def.depth -= 1,
+                _ if line.iter().any(|t| matches!(t, Token::Repeat(_))) => def.depth += 1,
+                _ => {}
+            }
+            def.mac.body.push(line.to_vec());
+            return;
+        }
+        if let Token::Directive(d, pos) = &line[0] {
+            let (val, val_pos) = match line.get(1) {
+                Some(Token::Value(v, p)) => (v.as_str(), *p),
+                _ => ("", *pos),
+            };
+            if d == "MACRO" {
+                self.define(val, *pos, val_pos);
+                return;
+            }
+            if d == "INCLUDE" {
+                self.include(val, val_pos);
+                return;
+            }
+            let err = |e| src.parse_err(*pos, e);
+            if self.seen_frame {
+                self.errs
+                    .push(err("Directives must come before the first frame."));
+                return;
+            }
+            if HEADER.contains(&d.as_str()) {
+                if self.header.contains(d) {
+                    self.errs.push(err("This header field is already set."));
+                    return;
+                }
+                self.header.push(d.clone());
+            }
+            match d.as_str() {
+                "FRAMERATE" => match val.parse() {
+                    Ok(f) => self.meta.framerate = f,
+                    Err(_) => {
+                        self.errs
+                            .push(src.parse_err(val_pos, "Invalid frame rate.").with_help(
+                                "use a whole number, a decimal or a fraction, e.g. `60000/1001`",
+                            ))
+                    }
+                },
+                "CONTROLLER" => match val.parse() {
+                    Ok(c) => self.meta.controller = c,
+                    Err(_) => self.errs.push(
+                        src.parse_err(val_pos, "Unknown controller type.")
+                            .with_help("the only controller type so far is `PRO`"),
+                    ),
+                },
+                _ => match self.meta.text_mut(d) {
+                    // quotes are only needed to keep a `#` or `//` from starting a comment
+                    Some(field) => match val.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                        Some(v) => *field = Some(v.to_owned()),
+                        None if val.is_empty() => {
+                            self.errs.push(err("This header field needs a value."))
+                        }
+                        None => *field = Some(val.to_owned()),
+                    },
+                    None => self
+                        .errs
+                        .push(suggest(err("Unknown directive."), d, &DIRECTIVES)),
+                },
+            }
+            return;
+        }
+        if let Some(Token::Param(_, pos)) = line.iter().find(|t| matches!(t, Token::Param(_, _))) {
+            self.errs.push(
+                src.parse_err(*pos, "Parameters can only be used inside a macro.")
+                    .with_help("macros are defined like `MACRO name(a, b) {`"),
+            );
+            return;
+        }
+        self.seen_frame = true;
+        if let Token::BlockClose(pos) = line[0] {
+            if line[1..].iter().any(|t| !matches!(t, Token::Whitespace(_))) {
+                self.errs
+                    .push(src.parse_err(pos, "Nothing else can go on the line ending a block."));
+            }
+            if self.blocks.len() == 1 {
+                self.errs
+                    .push(src.parse_err(pos, "There is no block to close here."));
+                return;
+            }
+            self.close_block();
+            return;
+        }
+        let absolute;
+        let line = match line[0] {
+            Token::Plus(pos) => match self.absolute(line, pos) {
+                Some(l) => {
+                    absolute = l;
+                    &absolute[..]
+                }
+                None => return,
+            },
+            _ => line,
+        };
+        let top = self.blocks.last_mut().unwrap();
+        // frame numbers that aren't valid are reported by `Line::get`
+        let frame = match line[0] {
+            Token::Number(n, pos) => n.frame().ok().map(|n| (n, pos)),
+            _ => None,
+        };
+        if let Some((n, pos)) = frame {
+            if let Some((f, help)) = top.last.as_ref().filter(|(f, _)| n <= *f) {
+                self.errs.push(
+                    src.parse_err(pos, "Frame numbers must strictly increase.")
+                        .with_help(format!("{} {}", help, f)),
+                );
+            }
+            top.last = Some((n, "the previous line is on frame"));
+        }
+        top.filled = true;
+        if let Some(r) = line.iter().find(|t| matches!(t, Token::Repeat(_))) {
+            let b = Block::open(line, &src).unwrap_or_else(|e| {
+                self.errs.push(e);
+                // stand in for the broken block so its `}` still has something to close
+                let mut b = Block::new(frame.map_or(0, |(f, _)| f), 1, r.pos());
+                b.filled = true;
+                b
+            });
+            self.blocks.push(b);
+            return;
+        }
+        if line.iter().any(|t| matches!(t, Token::Call(_, _))) {
+            if let Err(e) = self.call(line) {
+                self.errs.push(e);
+            }
+            return;
+        }
+        match Line::get(line, &src) {
+            // every frame needs one after it for its state to last
+            Ok(l) => match l.frame.checked_add(1) {
+                Some(end) => {
+                    top.end = top.end.max(end);
+                    top.lines.push(l);
+                }
+                None => self
+                    .errs
+                    .push(src.parse_err(line[0].pos(), "This frame number is too large.")),
+            },
+            Err(e) => self.errs.push(e),
+        }
+    }
+
+    /// `line` with its `+N` swapped for the frame N after the previous line, or N after the
+    /// start of the block if it's the first one.
+    fn absolute(&mut self, line: &[Token], pos: Pos) -> Option<Vec<Token>> {
+        let src = Rc::clone(&self.src);
+        let (n, end) = match line.get(1) {
+            Some(Token::Number(n, (_, _, end))) => match n.frame() {
+                Ok(n) => (n, *end),
+                Err(e) => {
+                    self.errs.push(src.parse_err(line[1].pos(), e));
+                    return None;
+                }
+            },
+            _ => {
+                self.errs
+                    .push(src.parse_err(pos, "Expected a number of frames after `+`."));
+                return None;
+            }
+        };
+        let last = self.blocks.last().unwrap().last.map_or(0, |(f, _)| f);
+        match last.checked_add(n) {
+            Some(f) => {
+                let mut l = vec![Token::Number(Num::Int(f as i128), (pos.0, pos.1, end))];
+                l.extend_from_slice(&line[2..]);
+                Some(l)
+            }
+            None => {
+                self.errs
+                    .push(src.parse_err((pos.0, pos.1, end), "This frame number is too large."));
+                None
+            }
+        }
+    }
+
+    fn close_block(&mut self) {
+        let b = self.blocks.pop().unwrap();
+        if let Err(e) = b.close(self.blocks.last_mut().unwrap(), &self.src) {
+            self.errs.push(e);
         }
-        Ok(Tas { lines })
+    }
+
+    /// Add the lines of the file named by `INCLUDE "path" [at FRAME]` to the current block.
+    /// Without a frame they start right after whatever came before them.
+    fn include(&mut self, args: &str, pos: Pos) {
+        let src = Rc::clone(&self.src);
+        let (path, at) = match include_args(args) {
+            Some(a) => a,
+            None => {
+                self.errs.push(
+                    src.parse_err(pos, "Malformed INCLUDE directive.")
+                        .with_help("files are included like `INCLUDE \"part.txt\" at 600`"),
+                );
+                return;
+            }
+        };
+        let top = self.blocks.last_mut().unwrap();
+        let at = match at.map(str::parse::<u64>) {
+            None => match top.last.map_or(Some(0), |(f, _)| f.checked_add(1)) {
+                Some(at) => at,
+                None => {
+                    self.errs
+                        .push(src.parse_err(pos, "This frame number is too large."));
+                    return;
+                }
+            },
+            Some(Ok(at)) => {
+                if let Some((f, help)) = top.last.as_ref().filter(|(f, _)| at <= *f) {
+                    self.errs.push(
+                        src.parse_err(pos, "Frame numbers must strictly increase.")
+                            .with_help(format!("{} {}", help, f)),
+                    );
+                }
+                at
+            }
+            Some(Err(_)) => {
+                self.errs.push(
+                    src.parse_err(pos, "Invalid frame number.")
+                        .with_help("the frame after `at` must be a whole number"),
+                );
+                return;
+            }
+        };
+        self.seen_frame = true;
+        top.filled = true;
+        // paths are relative to the file doing the including, not wherever tasc was run from
+        let path = src
+            .name
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(path);
+        let file = match read_to_string(&path) {
+            Ok(text) => SourceFile {
+                strict: src.strict,
+                ..SourceFile::new(text, Some(path.clone()))
+            },
+            Err(e) => {
+                self.errs.push(
+                    src.parse_err(pos, "Could not read the included file.")
+                        .with_help(format!("{}: {}", path.display(), e)),
+                );
+                return;
+            }
+        };
+        let real = canonicalize(&path).unwrap_or(path);
+        if self.includes.contains(&real) {
+            let chain: Vec<String> = self
+                .includes
+                .iter()
+                .chain(Some(&real))
+                .map(|p| {
+                    p.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+            self.errs.push(
+                src.parse_err(pos, "This file is already being included.")
+                    .with_help(chain.join(" -> ")),
+            );
+            return;
+        }
+        let tas = if let Format::NxTas = file.format() {
+            nxtas::parse(&file)
+        } else {
+            let (tok, errs) = lex(&file);
+            let mut parser = Parser::new(Rc::new(file));
+            parser.errs = errs;
+            parser.includes = self.includes.iter().cloned().chain(Some(real)).collect();
+            // macros go both ways, so a file of them can be included for its definitions
+            parser.macros = std::mem::take(&mut self.macros);
+            parser.calling = self.calling.clone();
+            parser.feed(tok);
+            self.macros = std::mem::take(&mut parser.macros);
+            parser.finish()
+        };
+        let lines = match tas {
+            Ok(tas) => tas.lines,
+            Err(e) => {
+                self.errs.extend(e);
+                return;
+            }
+        };
+        let mut b = Block::new(at, 1, pos);
+        b.end = match lines.last().map_or(Some(0), |l| l.frame.checked_add(1)) {
+            Some(end) => end,
+            None => {
+                self.errs
+                    .push(src.parse_err(pos, "This frame number is too large."));
+                return;
+            }
+        };
+        b.lines = lines;
+        b.filled = true;
+        if let Err(e) = b.close(self.blocks.last_mut().unwrap(), &src) {
+            self.errs.push(e);
+        }
+    }
+
+    /// Start reading the body of the macro whose `MACRO` line has `sig` after the directive.
+    fn define(&mut self, sig: &str, pos: Pos, sig_pos: Pos) {
+        let src = Rc::clone(&self.src);
+        let name = match signature(sig) {
+            _ if self.blocks.len() > 1 => {
+                self.errs
+                    .push(src.parse_err(pos, "Macros cannot be defined inside a block."));
+                None
+            }
+            Some((name, _)) if self.macros.contains_key(&name) => {
+                self.errs
+                    .push(src.parse_err(sig_pos, "A macro with this name already exists."));
+                None
+            }
+            Some((name, params)) => Some((name, params)),
+            None => {
+                self.errs.push(
+                    src.parse_err(sig_pos, "Malformed macro definition.")
+                        .with_help(
+                            "macros are defined like `MACRO name(a, b) {`, \
+                         with a name that starts with a lowercase letter",
+                        ),
+                );
+                None
+            }
+        };
+        let (name, params) = match name {
+            Some((n, p)) => (Some(n), p),
+            None => (None, vec![]),
+        };
+        self.defining = Some(Definition {
+            name,
+            pos,
+            mac: Macro {
+                params,
+                body: vec![],
+                src: Rc::clone(&src),
+            },
+            depth: 0,
+        });
+    }
+
+    /// Expand a `FRAME name(ARGS)` line in place, as a block starting on `FRAME`.
+    fn call(&mut self, line: &[Token]) -> Result<(), TasError> {
+        let src = Rc::clone(&self.src);
+        let toks: Vec<&Token> = line
+            .iter()
+            .filter(|t| !matches!(t, Token::Whitespace(_)))
+            .collect();
+        let malformed = |pos| {
+            src.parse_err(pos, "Malformed macro call.")
+                .with_help("macros are called like `10 name(90, KEY_A)`, alone on their line")
+        };
+        let (at, name, pos, args) = match &toks[..] {
+            [Token::Number(n, p), Token::Call(name, pos), Token::BracketOpen(_), args @ .., Token::BracketClose(_)]
+                if args.iter().all(|t| {
+                    matches!(t, Token::Number(_, _) | Token::Key(_, _) | Token::Comma(_))
+                }) =>
+            {
+                (
+                    n.frame().map_err(|e| src.parse_err(*p, e))?,
+                    name,
+                    *pos,
+                    args,
+                )
+            }
+            _ => {
+                let c = toks
+                    .iter()
+                    .find(|t| matches!(t, Token::Call(_, _)))
+                    .unwrap();
+                return Err(malformed(c.pos()));
+            }
+        };
+        // arguments alternate with commas
+        let args: Vec<Token> = args.iter().step_by(2).map(|t| (*t).clone()).collect();
+        let mac = match self.macros.get(name) {
+            Some(m) => m.clone(),
+            None => {
+                let known: Vec<&str> = self.macros.keys().map(String::as_str).collect();
+                return Err(suggest(src.parse_err(pos, "Unknown macro."), name, &known));
+            }
+        };
+        if mac.params.len() != args.len() {
+            return Err(src
+                .parse_err(pos, "Wrong number of arguments.")
+                .with_help(format!(
+                    "`{}` takes {} argument(s), but was given {}",
+                    name,
+                    mac.params.len(),
+                    args.len()
+                )));
+        }
+        if self.calling.contains(name) {
+            return Err(src.parse_err(pos, "Macros cannot call themselves."));
+        }
+        // arguments are checked here, where errors can still point at them in the calling file
+        for (param, arg) in mac.params.iter().zip(&args) {
+            for slot in mac.slots(param) {
+                if let Err(e) = slot.fits(arg) {
+                    return Err(src.parse_err(arg.pos(), e).with_help(format!(
+                        "`${}` stands for {} in `{}`",
+                        param,
+                        slot.name(),
+                        name
+                    )));
+                }
+            }
+        }
+        let mut b = Block::new(at, 1, pos);
+        b.filled = true;
+        self.blocks.push(b);
+        self.calling.push(name.clone());
+        // the body's lines are from wherever the macro was defined, maybe an included file
+        self.src = Rc::clone(&mac.src);
+        for l in &mac.body {
+            match substitute(l, &mac.params, &args, &mac.src) {
+                Ok(l) => self.line(&l),
+                Err(e) => self.errs.push(e),
+            }
+        }
+        self.src = src;
+        self.calling.pop();
+        self.close_block();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Tas, Vec<TasError>> {
+        if let Some(def) = self.defining.take() {
+            self.errs.push(
+                self.src
+                    .parse_err(def.pos, "This macro is never closed.")
+                    .with_help("macros end with a line holding just `}`"),
+            );
+        }
+        for b in self.blocks.drain(1..) {
+            self.errs.push(
+                self.src
+                    .parse_err(b.pos, "This block is never closed.")
+                    .with_help("blocks end with a line holding just `}`"),
+            );
+        }
+        if self.errs.is_empty() {
+            Ok(Tas {
+                meta: self.meta,
+                lines: self.blocks.pop().unwrap().lines,
+            })
+        } else {
+            Err(self.errs)
+        }
+    }
+}
+
+/// The path and frame, if there is one, in `"path" at FRAME`.
+fn include_args(args: &str) -> Option<(&str, Option<&str>)> {
+    let (path, rest) = args.strip_prefix('"')?.split_once('"')?;
+    let rest = rest.trim_start();
+    if rest.is_empty() {
+        return Some((path, None));
+    }
+    let frame = rest.strip_prefix("at ")?.trim_start();
+    Some((path, Some(frame)))
+}
+
+/// The name and parameters in `name(a, b) {`.
+fn signature(sig: &str) -> Option<(String, Vec<String>)> {
+    let (name, rest) = sig.split_once('(')?;
+    let (params, rest) = rest.split_once(')')?;
+    if rest.trim() != "{" {
+        return None;
+    }
+    let ident = |s: &str| {
+        s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let name = name.trim();
+    if !ident(name) || !name.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return None;
+    }
+    let params: Vec<String> = match params.trim() {
+        "" => vec![],
+        p => p.split(',').map(|p| p.trim().to_owned()).collect(),
+    };
+    if params.iter().all(|p| ident(p)) {
+        Some((name.to_owned(), params))
+    } else {
+        None
+    }
+}
+
+/// `line` with every parameter replaced by the argument given for it. The arguments take the
+/// place of the parameters in the body, so errors in them point somewhere in the same file.
+fn substitute(
+    line: &[Token],
+    params: &[String],
+    args: &[Token],
+    src: &SourceFile,
+) -> Result<Vec<Token>, TasError> {
+    line.iter()
+        .map(|t| match t {
+            Token::Param(name, pos) => match params.iter().position(|p| p == name) {
+                Some(i) => Ok(match &args[i] {
+                    Token::Number(n, _) => Token::Number(*n, *pos),
+                    Token::Key(k, _) => Token::Key(k.clone(), *pos),
+                    a => a.clone(),
+                }),
+                None => {
+                    let known: Vec<&str> = params.iter().map(String::as_str).collect();
+                    Err(suggest(
+                        src.parse_err(*pos, "Unknown macro parameter."),
+                        name,
+                        &known,
+                    ))
+                }
+            },
+            t => Ok(t.clone()),
+        })
+        .collect()
+}
+
+/// How many lines a script can hold once its blocks are expanded, a couple of days of input
+/// changing on every frame.
+const MAX_LINES: u64 = 10_000_000;
+
+/// A `REPEAT` block being parsed, or the whole script at the bottom of the stack of them.
+struct Block {
+    /// Frame the block starts on, relative to the block it's in.
+    at: u64,
+    count: u64,
+    pos: Pos,
+    /// Lines with frames relative to the start of the block.
+    lines: Vec<Line>,
+    /// One past the last frame of the block's body; one repetition lasts this long.
+    end: u64,
+    /// The last frame taken so far, and how to explain it if a line comes before it.
+    last: Option<(u64, &'static str)>,
+    /// Whether there's been a line in the block, even one that had errors.
+    filled: bool,
+}
+
+impl Block {
+    fn new(at: u64, count: u64, pos: Pos) -> Self {
+        Block {
+            at,
+            count,
+            pos,
+            lines: vec![],
+            end: 0,
+            last: None,
+            filled: false,
+        }
+    }
+
+    /// The block started by `FRAME REPEAT COUNT {`.
+    fn open(line: &[Token], src: &SourceFile) -> Result<Self, TasError> {
+        let toks: Vec<&Token> = line
+            .iter()
+            .filter(|t| !matches!(t, Token::Whitespace(_)))
+            .collect();
+        let (at, pos, count) = match toks[..] {
+            [Token::Number(n, p), Token::Repeat(pos), count, Token::BlockOpen(_)] => {
+                (n.frame().map_err(|e| src.parse_err(*p, e))?, *pos, count)
+            }
+            _ => {
+                let r = toks.iter().find(|t| matches!(t, Token::Repeat(_)));
+                return Err(src
+                    .parse_err(
+                        r.map_or(line[0].pos(), |r| r.pos()),
+                        "Malformed REPEAT block.",
+                    )
+                    .with_help("blocks start with a line like `10 REPEAT 5 {`"));
+            }
+        };
+        match *count {
+            Token::Number(Num::Int(n), _) if n > 0 && n <= u64::MAX as i128 => {
+                Ok(Block::new(at, n as u64, pos))
+            }
+            ref t => Err(src.parse_err(t.pos(), "Repeat count must be a positive whole number.")),
+        }
+    }
+
+    /// Add `count` copies of the block's lines to `parent`, one after the other.
+    fn close(self, parent: &mut Block, src: &SourceFile) -> Result<(), TasError> {
+        if !self.filled {
+            return Err(src.parse_err(self.pos, "This block has no lines in it."));
+        }
+        if self.lines.is_empty() {
+            // every line in it was broken, and has been reported already
+            return Ok(());
+        }
+        let len = self
+            .end
+            .checked_mul(self.count)
+            .and_then(|l| l.checked_add(self.at))
+            .ok_or_else(|| src.parse_err(self.pos, "This block runs for too many frames."))?
+            - self.at;
+        // every copy is kept in memory, so a big count on a short body can still be too much
+        let total = (self.lines.len() as u64)
+            .checked_mul(self.count)
+            .and_then(|n| n.checked_add(parent.lines.len() as u64))
+            .filter(|n| *n <= MAX_LINES);
+        if total.is_none() {
+            return Err(src
+                .parse_err(self.pos, "This block expands to too many lines.")
+                .with_help(format!(
+                    "a script can add up to {} lines once its blocks are repeated",
+                    MAX_LINES
+                )));
+        }
+        for i in 0..self.count {
+            parent.lines.extend(self.lines.iter().map(|l| Line {
+                frame: self.at + i * self.end + l.frame,
+                ..l.clone()
+            }));
+        }
+        parent.end = parent.end.max(self.at + len);
+        parent.last = Some((
+            self.at + len - 1,
+            "the block before this line runs until frame",
+        ));
+        Ok(())
+    }
+}
+
+impl FromStr for Tas {
+    type Err = Vec<TasError>;
+    /// Parse a script that isn't backed by a file; errors are reported against `<input>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SourceFile::new(s.to_owned(), None).parse()
+    }
+}
+
+impl<'a> IntoIterator for &'a Tas {
+    type Item = &'a Line;
+    type IntoIter = Iter<'a, Line>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.lines.iter()
     }
 }
+
 impl Display for Tas {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.meta)?;
         writeln!(
             f,
             "Frames   On               Off              Left Stick           Right Stick"
@@ -44,62 +979,94 @@ impl Display for Tas {
     }
 }
 
-#[derive(Debug)]
+/// One line of a script: the changes to make to the controller on a single frame.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Line {
-    pub delay: Duration,
-    pub on: u16,
-    pub off: u16,
+    /// Absolute frame this line takes effect on, counted from the start of the script.
+    pub frame: u64,
+    /// Buttons pressed on this frame.
+    pub on: Buttons,
+    /// Buttons released on this frame. Releases are applied before presses.
+    pub off: Buttons,
+    /// New left stick position, if it moves.
     pub lstick: Option<Stick>,
+    /// New right stick position, if it moves.
     pub rstick: Option<Stick>,
 }
 
 impl Line {
     fn new() -> Self {
         Line {
-            delay: Duration::ZERO,
-            on: key::NONE,
-            off: key::NONE,
+            frame: 0,
+            on: Buttons::empty(),
+            off: Buttons::empty(),
             lstick: None,
             rstick: None,
         }
     }
-    fn get(line: &[Token]) -> Result<Self, TasError> {
+    pub(crate) fn get(line: &[Token], src: &SourceFile) -> Result<Self, TasError> {
         let mut out = Line::new();
+        // keys turned off by OFF, as opposed to everything RAW turns off
+        let mut off = Buttons::empty();
         let mut line = line.iter();
+        match line.find(|t| !matches!(t, Token::Whitespace(_) | Token::Comment(_, _))) {
+            Some(Token::Number(n, pos)) => {
+                out.frame = n.frame().map_err(|e| src.parse_err(*pos, e))?;
+            }
+            Some(t) => {
+                return Err(src.parse_err(t.pos(), "Expected a frame number to start the line."))
+            }
+            None => {}
+        }
         while let Some(tok) = line.next() {
             match tok {
-                Token::Number(n, _) => {
-                    out.delay = Duration::from_nanos(16666666 * n);
+                Token::Number(_, pos) => {
+                    return Err(src.parse_err(
+                        *pos,
+                        "Frame numbers can only appear at the start of a line.",
+                    ));
                 }
-                Token::Operation(op, (l, c)) => match op.as_str() {
+                Token::Operation(op, pos) => match op.as_str() {
                     "ON" => {
                         line.next();
-                        out.on = get_keys(&mut line)?;
+                        out.on = get_keys(&mut line, src)?;
+                        if out.on.intersects(off) {
+                            return Err(src.parse_err(
+                                *pos,
+                                "Keys cannot be turned both on and off on the same line.",
+                            ));
+                        }
                     }
                     "OFF" => {
                         line.next();
-                        out.off = get_keys(&mut line)?;
+                        off = get_keys(&mut line, src)?;
+                        if out.on.intersects(off) {
+                            return Err(src.parse_err(
+                                *pos,
+                                "Keys cannot be turned both on and off on the same line.",
+                            ));
+                        }
+                        out.off = off;
                     }
                     "LSTICK" => {
                         line.next();
-                        out.lstick = Some(Stick::get(&mut line)?);
+                        out.lstick = Some(Stick::get(&mut line, *pos, src)?);
                     }
                     "RSTICK" => {
                         line.next();
-                        out.rstick = Some(Stick::get(&mut line)?);
+                        out.rstick = Some(Stick::get(&mut line, *pos, src)?);
                     }
                     "RAW" => {
                         line.next();
-                        out.off = key::ALL;
-                        out.on = get_keys(&mut line)?;
+                        out.off = Buttons::all() - Buttons::TRIGGERS;
+                        out.on = get_keys(&mut line, src)?;
                     }
                     _ => {
-                        return Err(TasError::Parse {
-                            l: *l,
-                            c: *c,
-                            e: "Unknown operation.",
-                            p: PATH.get().unwrap().into(),
-                        });
+                        return Err(suggest(
+                            src.parse_err(*pos, "Unknown operation."),
+                            op,
+                            &OPERATIONS,
+                        ));
                     }
                 },
                 _ => {}
@@ -111,121 +1078,234 @@ impl Line {
 
 impl Display for Line {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
-        let fr = " ".repeat(8 - format!("{}", self.delay.as_nanos() / 16666666).len());
-        let o1 = " ".repeat(16 - format!("{:b}", self.on).len());
-        let o2 = " ".repeat(16 - format!("{:b}", self.off).len());
-        let l = " ".repeat(20 - format!("{}", self.lstick.unwrap_or(Stick::new())).len());
+        let fr = " ".repeat(8 - format!("{}", self.frame).len());
+        let o1 = " ".repeat(16 - format!("{:b}", self.on.bits()).len());
+        let o2 = " ".repeat(16 - format!("{:b}", self.off.bits()).len());
+        let l = " ".repeat(20 - format!("{}", self.lstick.unwrap_or_default()).len());
         write!(
             f,
             "{}{} {:b}{} {:b}{} {}{} {}",
-            self.delay.as_nanos() / 16666666,
+            self.frame,
             fr,
-            self.on,
+            self.on.bits(),
             o1,
-            self.off,
+            self.off.bits(),
             o2,
-            self.lstick.unwrap_or(Stick::new()),
+            self.lstick.unwrap_or_default(),
             l,
-            self.rstick.unwrap_or(Stick::new())
+            self.rstick.unwrap_or_default()
         )
     }
 }
 
-fn get_keys(line: &mut Iter<Token>) -> Result<u16, TasError> {
-    let mut keys = key::NONE;
+fn get_keys(line: &mut Iter<Token>, src: &SourceFile) -> Result<Buttons, TasError> {
+    let mut keys = Buttons::empty();
     for tok in line {
-        if let Token::Key(k, (l, c)) = tok {
-            if let Some(n) = key2u16(k) {
+        if let Token::Key(k, pos) = tok {
+            if let Some(n) = key_to_buttons(k) {
                 keys |= n;
             } else {
-                return Err(TasError::Parse {
-                    l: *l,
-                    c: *c,
-                    e: "Unknown key identifier.",
-                    p: PATH.get().unwrap().into(),
-                });
+                return Err(suggest(
+                    src.parse_err(*pos, "Unknown key identifier."),
+                    k,
+                    &KEYS,
+                ));
             }
         } else if let Token::BracketClose(_) = tok {
             break;
+        } else if !matches!(tok, Token::Comma(_)) {
+            return Err(src.parse_err(tok.pos(), "Expected a key identifier."));
         }
     }
     Ok(keys)
 }
 
-fn key2u16(key: &str) -> Option<u16> {
+/// The directives that make up the header.
+const HEADER: [&str; 8] = [
+    "TITLE",
+    "AUTHOR",
+    "GAME",
+    "GAME_VERSION",
+    "EMULATOR",
+    "FRAMERATE",
+    "CONTROLLER",
+    "START",
+];
+const DIRECTIVES: [&str; 10] = [
+    "TITLE",
+    "AUTHOR",
+    "GAME",
+    "GAME_VERSION",
+    "EMULATOR",
+    "FRAMERATE",
+    "CONTROLLER",
+    "START",
+    "MACRO",
+    "INCLUDE",
+];
+const OPERATIONS: [&str; 5] = ["ON", "OFF", "LSTICK", "RSTICK", "RAW"];
+pub(crate) const KEYS: [&str; 18] = [
+    "KEY_A",
+    "KEY_B",
+    "KEY_X",
+    "KEY_Y",
+    "KEY_L",
+    "KEY_R",
+    "KEY_ZL",
+    "KEY_ZR",
+    "KEY_DUP",
+    "KEY_DDOWN",
+    "KEY_DLEFT",
+    "KEY_DRIGHT",
+    "KEY_PLUS",
+    "KEY_MINUS",
+    "KEY_LSTICK",
+    "KEY_RSTICK",
+    "NONE",
+    "ALL",
+];
+
+pub(crate) fn key_to_buttons(key: &str) -> Option<Buttons> {
     if key.starts_with('K') {
         let key = key.split_once('_')?.1;
         match key {
-            "A" => Some(key::A),
-            "B" => Some(key::B),
-            "X" => Some(key::X),
-            "Y" => Some(key::Y),
-            "L" => Some(key::L),
-            "R" => Some(key::R),
-            "ZL" => Some(key::ZL),
-            "ZR" => Some(key::ZR),
-            "DUP" => Some(key::DUP),
-            "DDOWN" => Some(key::DDOWN),
-            "DLEFT" => Some(key::DLEFT),
-            "DRIGHT" => Some(key::DRIGHT),
-            "PLUS" => Some(key::PLUS),
-            "MINUS" => Some(key::MINUS),
-            "LSTICK" => Some(key::LSTICK),
-            "RSTICK" => Some(key::RSTICK),
+            "A" => Some(Buttons::A),
+            "B" => Some(Buttons::B),
+            "X" => Some(Buttons::X),
+            "Y" => Some(Buttons::Y),
+            "L" => Some(Buttons::L),
+            "R" => Some(Buttons::R),
+            "ZL" => Some(Buttons::ZL),
+            "ZR" => Some(Buttons::ZR),
+            "DUP" => Some(Buttons::DUP),
+            "DDOWN" => Some(Buttons::DDOWN),
+            "DLEFT" => Some(Buttons::DLEFT),
+            "DRIGHT" => Some(Buttons::DRIGHT),
+            "PLUS" => Some(Buttons::PLUS),
+            "MINUS" => Some(Buttons::MINUS),
+            "LSTICK" => Some(Buttons::LSTICK),
+            "RSTICK" => Some(Buttons::RSTICK),
             _ => None,
         }
     } else {
         match key {
-            "NONE" => Some(key::NONE),
-            "ALL" => Some(key::ALL),
+            "NONE" => Some(Buttons::empty()),
+            // scripts have always left the triggers out of ALL
+            "ALL" => Some(Buttons::all() - Buttons::TRIGGERS),
             _ => None,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Position of an analog stick. Both axes go from -32768 to 32767, with y pointing up.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct Stick {
-    pub x: i16,
-    pub y: i16,
+    x: i16,
+    y: i16,
 }
 
 impl Stick {
-    fn new() -> Self {
-        Stick { x: 0, y: 0 }
-    }
-    fn get(line: &mut Iter<Token>) -> Result<Self, TasError> {
-        let mut stick = Stick::new();
-        let mut ang: f64 = f64::NAN;
-        let mut l: usize = 0;
-        let mut c: usize = 0;
-        if let Some(Token::Number(a, (lin, col))) = line.next() {
-            l = *lin;
-            c = *col;
-            ang = ((*a as f64) * std::f64::consts::PI) / 180.0;
-        }
-        // skip comma
-        line.next();
-        if let Some(Token::Number(m, (l, c))) = line.next() {
-            if !ang.is_nan() {
-                stick.x = (ang.sin() * *m as f64).ceil() as i16;
-                stick.y = (ang.cos() * *m as f64).ceil() as i16;
-            } else {
-                return Err(TasError::Parse {
-                    l: *l,
-                    c: *c,
-                    e: "Malformed stick information.",
-                    p: PATH.get().unwrap().into(),
-                });
-            }
+    pub fn new(x: i16, y: i16) -> Self {
+        Stick { x, y }
+    }
+
+    /// The stick tilted `magnitude` away from the center, at `angle` degrees clockwise from
+    /// straight up. This is how scripts describe stick positions.
+    pub fn from_polar(angle: f64, magnitude: f64) -> Self {
+        let ang = (angle * std::f64::consts::PI) / 180.0;
+        Stick {
+            x: (ang.sin() * magnitude).ceil() as i16,
+            y: (ang.cos() * magnitude).ceil() as i16,
+        }
+    }
+
+    pub fn x(&self) -> i16 {
+        self.x
+    }
+
+    pub fn y(&self) -> i16 {
+        self.y
+    }
+
+    /// Angle in degrees clockwise from straight up, between 0 and 360.
+    pub fn angle(&self) -> f64 {
+        let a = (self.x as f64).atan2(self.y as f64).to_degrees();
+        if a < 0.0 {
+            a + 360.0
         } else {
-            return Err(TasError::Parse {
-                l,
-                c,
-                e: "Malformed stick information.",
-                p: PATH.get().unwrap().into(),
-            });
+            a
         }
-        Ok(stick)
+    }
+
+    /// Distance from the center.
+    pub fn magnitude(&self) -> f64 {
+        (self.x as f64).hypot(self.y as f64)
+    }
+
+    fn get(line: &mut Iter<Token>, pos: Pos, src: &SourceFile) -> Result<Self, TasError> {
+        match line.next() {
+            Some(Token::Number(a, _)) => {
+                // skip comma
+                line.next();
+                let m = match line.next() {
+                    Some(Token::Number(m, pos)) => {
+                        let m = m.as_f64();
+                        if m < 0.0 {
+                            return Err(src.parse_err(*pos, "Stick magnitude cannot be negative."));
+                        } else if m > i16::MAX as f64 {
+                            return Err(
+                                src.parse_err(*pos, "Stick magnitude cannot be more than 32767.")
+                            );
+                        }
+                        m
+                    }
+                    tok => return Err(malformed_stick(src, pos, tok)),
+                };
+                Ok(Stick::from_polar(a.as_f64(), m))
+            }
+            first @ Some(Token::Axis(_, _)) => {
+                let (a, v) = get_coord(first, line.next(), pos, src)?;
+                // skip comma
+                line.next();
+                let (b, w) = get_coord(line.next(), line.next(), pos, src)?;
+                match (a, b) {
+                    ('x', 'y') => Ok(Stick::new(v, w)),
+                    ('y', 'x') => Ok(Stick::new(w, v)),
+                    _ => Err(src
+                        .parse_err(pos, "Stick axis given twice.")
+                        .with_help("give one `x` and one `y`")),
+                }
+            }
+            tok => Err(malformed_stick(src, pos, tok)),
+        }
+    }
+}
+
+// point at whatever was there instead of a number, or at the operation if the line ended
+fn malformed_stick(src: &SourceFile, pos: Pos, tok: Option<&Token>) -> TasError {
+    src.parse_err(tok.map_or(pos, Token::pos), "Malformed stick information.")
+        .with_help("stick parameters are written as `{angle,magnitude}` or `{x=X,y=Y}`")
+}
+
+/// One `x=X` or `y=Y` of an exact stick position.
+fn get_coord(
+    axis: Option<&Token>,
+    value: Option<&Token>,
+    pos: Pos,
+    src: &SourceFile,
+) -> Result<(char, i16), TasError> {
+    let a = match axis {
+        Some(Token::Axis(a, _)) => *a,
+        tok => return Err(malformed_stick(src, pos, tok)),
+    };
+    match value {
+        Some(Token::Number(Num::Int(v), p)) => i16::try_from(*v)
+            .map(|v| (a, v))
+            .map_err(|_| src.parse_err(*p, "Stick coordinates must be between -32768 and 32767.")),
+        Some(Token::Number(Num::Decimal(_), p)) => {
+            Err(src.parse_err(*p, "Stick coordinates must be whole numbers."))
+        }
+        tok => Err(malformed_stick(src, pos, tok)),
     }
 }
 
@@ -235,222 +1315,879 @@ impl Display for Stick {
     }
 }
 
-#[derive(Debug)]
-enum Token {
-    Number(u64, (usize, usize)),
-    Operation(String, (usize, usize)),
-    BracketOpen((usize, usize)),
-    BracketClose((usize, usize)),
-    Key(String, (usize, usize)),
-    Comma((usize, usize)),
-    Newline((usize, usize)),
-    Whitespace((usize, usize)),
-}
-
-// bitflags "enum"
-mod key {
-    pub const NONE: u16 = 0b0;
-    pub const DUP: u16 = 0x1;
-    pub const DDOWN: u16 = 0x2;
-    pub const DLEFT: u16 = 0x4;
-    pub const DRIGHT: u16 = 0x8;
-    pub const MINUS: u16 = 0x10;
-    pub const PLUS: u16 = 0x20;
-    pub const LSTICK: u16 = 0x40;
-    pub const RSTICK: u16 = 0x80;
-    pub const L: u16 = 0x100;
-    pub const R: u16 = 0x200;
-    // switch has no GUIDE button so skip that value, and use it and the next for ZL and ZR
-    pub const ZL: u16 = 0x400;
-    pub const ZR: u16 = 0x800;
-    pub const A: u16 = 0x1000;
-    pub const B: u16 = 0x2000;
-    pub const X: u16 = 0x4000;
-    pub const Y: u16 = 0x8000;
-    pub const ALL: u16 = 0xF3FF;
-}
-
-fn lex(input: String) -> Result<Vec<Token>, TasError> {
+/// Line, first column and one past the last column of a token.
+type Pos = (usize, usize, usize);
+
+/// A number as written: whole, or with a decimal point.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Num {
+    Int(i128),
+    Decimal(f64),
+}
+
+impl Display for Num {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Num::Int(n) => write!(f, "{}", n),
+            // keep the point on whole decimals, so they read back the same
+            Num::Decimal(n) => write!(f, "{:?}", n),
+        }
+    }
+}
+
+impl Num {
+    fn as_f64(self) -> f64 {
+        match self {
+            Num::Int(n) => n as f64,
+            Num::Decimal(n) => n,
+        }
+    }
+
+    pub(crate) fn frame(self) -> Result<u64, &'static str> {
+        match self {
+            Num::Int(n) if n < 0 => Err("Frame numbers cannot be negative."),
+            Num::Int(n) => u64::try_from(n).map_err(|_| "Number is too large."),
+            Num::Decimal(_) => Err("Frame numbers must be whole numbers."),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Token {
+    Number(Num, Pos),
+    Operation(String, Pos),
+    BracketOpen(Pos),
+    BracketClose(Pos),
+    Key(String, Pos),
+    Comma(Pos),
+    Newline(Pos),
+    Whitespace(Pos),
+    /// The `+` before a number of frames counted from the previous line.
+    Plus(Pos),
+    Directive(String, Pos),
+    Value(String, Pos),
+    /// `REPEAT`, followed by its count and `BlockOpen`.
+    Repeat(Pos),
+    BlockOpen(Pos),
+    BlockClose(Pos),
+    /// The name of a macro being called, followed by its arguments in brackets.
+    Call(String, Pos),
+    /// A macro parameter like `$angle`, without the `$`.
+    Param(String, Pos),
+    /// The `x=` or `y=` before one coordinate of an exact stick position.
+    Axis(char, Pos),
+    /// A comment, delimiters and all. Comments are kept for tools that rewrite scripts, and
+    /// otherwise ignored.
+    Comment(String, Pos),
+}
+
+impl Token {
+    fn pos(&self) -> Pos {
+        match self {
+            Token::Number(_, p)
+            | Token::Operation(_, p)
+            | Token::Key(_, p)
+            | Token::Directive(_, p)
+            | Token::Value(_, p)
+            | Token::Axis(_, p)
+            | Token::Call(_, p)
+            | Token::Param(_, p)
+            | Token::Comment(_, p) => *p,
+            Token::BracketOpen(p)
+            | Token::BracketClose(p)
+            | Token::Comma(p)
+            | Token::Repeat(p)
+            | Token::BlockOpen(p)
+            | Token::BlockClose(p)
+            | Token::Newline(p)
+            | Token::Whitespace(p)
+            | Token::Plus(p) => *p,
+        }
+    }
+}
+
+const UNEXPECTED: &str = "Unexpected character.";
+const LINE_START: &str = "A frame number or directive must appear at the start of each line.";
+
+/// Explain an unexpected character, most likely the start of a keyword written in lowercase.
+fn unexpected_help(src: &SourceFile, err: TasError, chr: char, it: &Peekable<Chars>) -> TasError {
+    if chr == '\t' {
+        return err.with_help("operations are separated by spaces, not tabs");
+    }
+    let word: String = std::iter::once(chr)
+        .chain(
+            it.clone()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_'),
+        )
+        .collect();
+    let upper = word.to_ascii_uppercase();
+    let known = OPERATIONS
+        .iter()
+        .chain(DIRECTIVES.iter())
+        .chain(KEYS.iter())
+        .find(|k| **k == upper);
+    match (err, known) {
+        (TasError::Syntax { l, c, e, .. }, Some(k)) if upper != word => src
+            .syntax_err((l, c, c + word.len()), e)
+            .with_help(format!("keywords are written in uppercase: `{}`", k)),
+        (err, _) => err,
+    }
+}
+
+/// Whether `first` and the characters after it are a macro name followed by `(`.
+fn call_ahead(first: char, it: &Peekable<Chars>) -> bool {
+    let mut rest = it
+        .clone()
+        .skip_while(|c| c.is_ascii_alphanumeric() || *c == '_');
+    first.is_ascii_lowercase() && rest.next() == Some('(')
+}
+
+/// Whether the next characters start a `#`, `//` or `/*` comment.
+fn comment_ahead(it: &Peekable<Chars>) -> bool {
+    let mut ahead = it.clone();
+    match ahead.next() {
+        Some('#') => true,
+        Some('/') => matches!(ahead.next(), Some('/' | '*')),
+        _ => false,
+    }
+}
+
+/// The `COUNT {` after a `REPEAT`, which `col` is the column of.
+fn lex_repeat(
+    it: &mut Peekable<Chars>,
+    out: &mut Vec<Token>,
+    line: usize,
+    col: &mut usize,
+) -> Result<(), &'static str> {
+    let skip_spaces = |it: &mut Peekable<Chars>, col: &mut usize| {
+        while it.peek() == Some(&' ') {
+            it.next();
+            *col += 1;
+        }
+    };
+    skip_spaces(it, col);
+    let nstart = *col + 1;
+    let mut num = String::new();
+    while let Some(d) = it.peek().filter(|c| c.is_ascii_digit()) {
+        num.push(*d);
+        it.next();
+    }
+    *col += num.len();
+    skip_spaces(it, col);
+    match num.parse() {
+        Ok(n) if it.peek() == Some(&'{') => {
+            out.push(Token::Number(
+                Num::Int(n),
+                (line, nstart, nstart + num.len()),
+            ));
+            it.next();
+            *col += 1;
+            out.push(Token::BlockOpen((line, *col, *col + 1)));
+            Ok(())
+        }
+        Ok(_) => Err("Expected `{` after the repeat count."),
+        Err(_) if num.is_empty() => Err("Expected a repeat count after `REPEAT`."),
+        Err(_) => Err("Number is too large."),
+    }
+}
+
+/// Split `input` into tokens. A line with a syntax error is dropped entirely and lexing picks
+/// back up at the start of the next one, so every broken line gets reported.
+pub(crate) fn lex(src: &SourceFile) -> (Vec<Token>, Vec<TasError>) {
     let mut out = vec![];
-    let mut it = input.chars().peekable();
+    let mut errs = vec![];
+    let mut it = src.text.chars().peekable();
     let mut line = 1;
     let mut col = 0;
     let mut bracketed = false;
-    let i = PATH.get().unwrap();
+    // whether the brackets are the parentheses around macro arguments
+    let mut paren = false;
+    // index into `out` of the first token on the current line
+    let mut line_start = 0;
     while let Some(chr) = it.next() {
-        match chr {
-            '+' => {
-                if line != 1 || col != 0 {
-                    return Err(TasError::Syntax {
-                        l: line,
-                        c: col,
-                        e: "`+` can only appear at the start of the script",
-                        p: i.into(),
-                    });
+        let start = col;
+        // comments don't count, so a line can start with one
+        let first_on_line = out[line_start..]
+            .iter()
+            .all(|t| matches!(t, Token::Comment(_, _) | Token::Whitespace(_)));
+        let comment = chr == '#' || chr == '/' && matches!(it.peek(), Some('/' | '*'));
+        let res = match chr {
+            _ if col == 0
+                && line != 1
+                && !matches!(chr, '0'..='9' | 'A'..='Z' | '#' | '/' | '}' | '$' | '+') =>
+            {
+                Err(LINE_START)
+            }
+            'A'..='Z' if first_on_line => {
+                let mut name = String::from(chr);
+                while let Some(c) = it.peek().filter(|&c| c.is_ascii_uppercase() || *c == '_') {
+                    name.push(*c);
+                    it.next();
+                    col += 1;
+                }
+                out.push(Token::Directive(name, (line, start, col + 1)));
+                // everything up to the end of the line is the directive's argument
+                while it.peek() == Some(&' ') {
+                    it.next();
+                    col += 1;
+                }
+                let vstart = col + 1;
+                let mut val = String::new();
+                // a quoted path can hold anything that would otherwise start a comment
+                let mut quoted = false;
+                while it.peek().is_some_and(|c| *c != '\n') && (quoted || !comment_ahead(&it)) {
+                    quoted ^= it.peek() == Some(&'"');
+                    val.extend(it.next());
+                    col += 1;
                 }
+                let val = val.trim_end().to_owned();
+                let vend = vstart + val.chars().count();
+                out.push(Token::Value(val, (line, vstart, vend)));
+                Ok(())
             }
-            ' ' => out.push(Token::Whitespace((line, col))),
-            '\n' => {
-                if bracketed {
-                    return Err(TasError::Syntax {
-                        l: line,
-                        c: col,
-                        e: "Newlines cannot appear in brackets.",
-                        p: i.into(),
-                    });
-                } else if !matches!(it.peek(), Some('0'..='9')) && it.peek() != None {
-                    return Err(TasError::Syntax {
-                        l: line,
-                        c: col,
-                        e: "A frame number must appear at the start of each line.",
-                        p: i.into(),
-                    });
+            '#' | '/' if comment && bracketed => Err("Comments cannot appear in brackets."),
+            '#' | '/' if chr == '#' || it.peek() == Some(&'/') => {
+                let mut text = String::from(chr);
+                while let Some(c) = it.peek().filter(|&c| *c != '\n') {
+                    text.push(*c);
+                    it.next();
+                    col += 1;
                 }
-                out.push(Token::Newline((line, col)));
+                out.push(Token::Comment(
+                    text.trim_end().to_owned(),
+                    (line, start, col + 1),
+                ));
+                Ok(())
+            }
+            '/' if comment => {
+                let first = line;
+                let mut text = String::from(chr);
+                // column the next character will be on
+                let mut next = col + 1;
+                let mut closed = false;
+                for c in it.by_ref() {
+                    text.push(c);
+                    if c == '\n' {
+                        line += 1;
+                        next = 0;
+                        continue;
+                    }
+                    next += 1;
+                    if text.len() > 3 && text.ends_with("*/") {
+                        closed = true;
+                        break;
+                    }
+                }
+                if closed {
+                    let end = match text.find('\n') {
+                        Some(n) => start + n,
+                        None => next,
+                    };
+                    out.push(Token::Comment(text, (first, start, end)));
+                    col = next - 1;
+                } else {
+                    errs.push(
+                        src.syntax_err((first, start, start + 2), "Unterminated block comment.")
+                            .with_help("block comments end with `*/`"),
+                    );
+                }
+                Ok(())
+            }
+            '+' if !bracketed && first_on_line && it.peek().is_some_and(char::is_ascii_digit) => {
+                out.push(Token::Plus((line, col, col + 1)));
+                Ok(())
+            }
+            '+' if line != 1 || col != 0 => {
+                Err("`+` can only appear at the start of a line, before a number of frames.")
+            }
+            // a script may open with a `+` line, which is accepted but means nothing
+            '+' => Ok(()),
+            // arguments can be spaced out freely
+            ' ' if paren => Ok(()),
+            ' ' => {
+                out.push(Token::Whitespace((line, col, col + 1)));
+                Ok(())
+            }
+            '\n' if bracketed => Err("Newlines cannot appear in brackets."),
+            '\n' => {
+                out.push(Token::Newline((line, col, col + 1)));
                 line += 1;
                 col = 0;
+                line_start = out.len();
+                continue;
             }
             '{' => {
-                if bracketed || !matches!(out[out.len() - 1], Token::Operation(_, _)) {
-                    return Err(TasError::Syntax {
-                        l: line,
-                        c: col,
-                        e: "Unexpected opening bracket.",
-                        p: i.into(),
-                    });
+                if bracketed || !matches!(out.last(), Some(Token::Operation(_, _))) {
+                    Err("Unexpected opening bracket.")
+                } else {
+                    out.push(Token::BracketOpen((line, col, col + 1)));
+                    bracketed = true;
+                    Ok(())
                 }
-                out.push(Token::BracketOpen((line, col)));
-                bracketed = true;
+            }
+            '}' if !bracketed && first_on_line => {
+                out.push(Token::BlockClose((line, start, col + 1)));
+                Ok(())
             }
             '}' => {
-                let last_tok = &out[out.len() - 1];
                 if !bracketed
-                    || (!matches!(last_tok, Token::Key(_, _))
-                        && !(matches!(last_tok, Token::Number(_, _)) && bracketed))
+                    || paren
+                    || !matches!(
+                        out.last(),
+                        Some(Token::Key(_, _) | Token::Number(_, _) | Token::Param(_, _))
+                    )
                 {
-                    return Err(TasError::Syntax {
-                        l: line,
-                        c: col,
-                        e: "Unexpected closing bracket.",
-                        p: i.into(),
-                    });
+                    Err("Unexpected closing bracket.")
+                } else {
+                    out.push(Token::BracketClose((line, col, col + 1)));
+                    bracketed = false;
+                    Ok(())
                 }
-                out.push(Token::BracketClose((line, col)));
-                bracketed = false;
             }
             ',' => {
-                let last_tok = &out[out.len() - 1];
-                if !matches!(last_tok, Token::Key(_, _))
-                    && !(matches!(last_tok, Token::Number(_, _)) && bracketed)
+                let last_tok = out.last();
+                if !(matches!(last_tok, Some(Token::Key(_, _)))
+                    || bracketed
+                        && matches!(last_tok, Some(Token::Number(_, _) | Token::Param(_, _))))
                 {
-                    return Err(TasError::Syntax {
-                        l: line,
-                        c: col,
-                        e: "Commas can only appear inside brackets.",
-                        p: i.into(),
-                    });
+                    Err("Commas can only appear inside brackets.")
+                } else {
+                    out.push(Token::Comma((line, col, col + 1)));
+                    Ok(())
                 }
-                out.push(Token::Comma((line, col)));
-            }
-            '0'..='9' => {
-                let last_tok = &out[out.len() - 1];
-                if !bracketed {
-                    if let Token::Newline(_) = last_tok {
-                        let mut num = String::from(chr);
-                        while let Some(d) = it.peek().filter(|c| c.is_ascii_digit()) {
-                            num.push(*d);
-                            it.next();
-                            col += 1;
-                        }
-                        out.push(Token::Number(num.parse().unwrap(), (line, col)));
+            }
+            '(' => {
+                if !matches!(out.last(), Some(Token::Call(_, _))) {
+                    Err("Unexpected opening parenthesis.")
+                } else {
+                    out.push(Token::BracketOpen((line, col, col + 1)));
+                    bracketed = true;
+                    paren = true;
+                    Ok(())
+                }
+            }
+            ')' => {
+                let last_tok = out.last();
+                if !paren
+                    || !matches!(
+                        last_tok,
+                        Some(
+                            Token::Key(_, _)
+                                | Token::Number(_, _)
+                                | Token::Param(_, _)
+                                | Token::BracketOpen(_)
+                        )
+                    )
+                {
+                    Err("Unexpected closing parenthesis.")
+                } else {
+                    out.push(Token::BracketClose((line, col, col + 1)));
+                    bracketed = false;
+                    paren = false;
+                    Ok(())
+                }
+            }
+            '$' => {
+                let last_tok = out.last();
+                if !bracketed && !first_on_line {
+                    Err("Parameters can only stand in for frame numbers, stick parameters, keys and macro arguments.")
+                } else if bracketed
+                    && !matches!(
+                        last_tok,
+                        Some(Token::Comma(_) | Token::BracketOpen(_) | Token::Axis(_, _))
+                    )
+                {
+                    Err("Expected one of `{` or `,` before parameter.")
+                } else {
+                    let mut name = String::new();
+                    while let Some(c) = it
+                        .peek()
+                        .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    {
+                        name.push(*c);
+                        it.next();
+                        col += 1;
+                    }
+                    if name.is_empty() {
+                        Err("Expected a parameter name after `$`.")
                     } else {
-                        return Err(TasError::Syntax {
-                            l: line,
-                            c: col,
-                            e: "Frame numbers can only appear at the start of a line.",
-                            p: i.into(),
-                        });
+                        out.push(Token::Param(name, (line, start, col + 1)));
+                        Ok(())
                     }
+                }
+            }
+            c if c.is_ascii_lowercase()
+                && !bracketed
+                && matches!(
+                    out.last(),
+                    Some(Token::Whitespace(_) | Token::Comment(_, _))
+                )
+                && call_ahead(c, &it) =>
+            {
+                let mut name = String::from(c);
+                while let Some(c) = it
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+                {
+                    name.push(*c);
+                    it.next();
+                    col += 1;
+                }
+                out.push(Token::Call(name, (line, start, col + 1)));
+                Ok(())
+            }
+            'x' | 'y' if bracketed => {
+                if !matches!(out.last(), Some(Token::Comma(_) | Token::BracketOpen(_))) {
+                    Err("Expected one of `{` or `,` before stick parameter.")
+                } else if it.peek() != Some(&'=') {
+                    Err("Expected `=` after stick axis.")
                 } else {
-                    if let Token::Comma(_) | Token::BracketOpen(_) = last_tok {
-                        let mut num = String::from(chr);
-                        while let Some(d) = it.peek().filter(|c| c.is_ascii_digit()) {
-                            num.push(*d);
-                            it.next();
-                            col += 1;
-                        }
-                        out.push(Token::Number(num.parse().unwrap(), (line, col)));
+                    it.next();
+                    col += 1;
+                    out.push(Token::Axis(chr, (line, start, col + 1)));
+                    Ok(())
+                }
+            }
+            c if c.is_ascii_digit() || c == '-' && bracketed => {
+                let last_tok = out.last();
+                if !bracketed && !first_on_line && !matches!(last_tok, Some(Token::Plus(_))) {
+                    Err("Frame numbers can only appear at the start of a line.")
+                } else if bracketed
+                    && !matches!(
+                        last_tok,
+                        Some(Token::Comma(_) | Token::BracketOpen(_) | Token::Axis(_, _))
+                    )
+                {
+                    Err("Expected one of `{` or `,` before stick parameter.")
+                } else {
+                    let mut num = String::from(chr);
+                    let mut point = false;
+                    while let Some(d) = it
+                        .peek()
+                        .filter(|c| c.is_ascii_digit() || **c == '.' && !point)
+                    {
+                        point |= *d == '.';
+                        num.push(*d);
+                        it.next();
+                        col += 1;
+                    }
+                    if !num.ends_with(|c: char| c.is_ascii_digit()) {
+                        Err("Expected a digit.")
+                    } else if point {
+                        // digits with one `.` between them always parse
+                        out.push(Token::Number(
+                            Num::Decimal(num.parse().unwrap()),
+                            (line, start, col + 1),
+                        ));
+                        Ok(())
                     } else {
-                        return Err(TasError::Syntax {
-                            l: line,
-                            c: col,
-                            e: "Expected one of `{` or `,` before stick parameter.",
-                            p: i.into(),
-                        });
+                        match num.parse() {
+                            Ok(n) => {
+                                out.push(Token::Number(Num::Int(n), (line, start, col + 1)));
+                                Ok(())
+                            }
+                            Err(_) => Err("Number is too large."),
+                        }
                     }
                 }
             }
             'K' | 'A' | 'N' => {
-                let last_tok = &out[out.len() - 1];
-                if let Token::BracketOpen(_) | Token::Comma(_) = last_tok {
+                if let Some(Token::BracketOpen(_) | Token::Comma(_)) = out.last() {
                     let mut key = String::from(chr);
                     while let Some(c) = it.peek().filter(|&c| c.is_ascii_uppercase() || *c == '_') {
                         key.push(*c);
                         it.next();
                         col += 1;
                     }
-                    out.push(Token::Key(key, (line, col)));
+                    out.push(Token::Key(key, (line, start, col + 1)));
+                    Ok(())
                 } else {
-                    return Err(TasError::Syntax {
-                        l: line,
-                        c: col,
-                        e: "Expected one of `{` or `,` before key identifier.",
-                        p: i.into(),
-                    });
+                    Err("Expected one of `{` or `,` before key identifier.")
                 }
             }
             'O' | 'R' | 'L' => {
-                let last_tok = &out[out.len() - 1];
-                if !bracketed {
-                    if let Token::Whitespace(_) = last_tok {
-                        let mut op = String::from(chr);
-                        while let Some(c) = it.peek().filter(|c| c.is_ascii_uppercase()) {
-                            op.push(*c);
-                            it.next();
-                            col += 1;
-                        }
-                        out.push(Token::Operation(op, (line, col)));
+                if bracketed {
+                    Err("Operations cannot appear inside brackets")
+                } else if let Some(Token::Whitespace(_) | Token::Comment(_, _)) = out.last() {
+                    let mut op = String::from(chr);
+                    while let Some(c) = it.peek().filter(|c| c.is_ascii_uppercase()) {
+                        op.push(*c);
+                        it.next();
+                        col += 1;
+                    }
+                    if op == "REPEAT" {
+                        out.push(Token::Repeat((line, start, col + 1)));
+                        lex_repeat(&mut it, &mut out, line, &mut col)
                     } else {
-                        return Err(TasError::Syntax {
-                            l: line,
-                            c: col,
-                            e: "Expected whitespace before operation.",
-                            p: i.into(),
-                        });
+                        out.push(Token::Operation(op, (line, start, col + 1)));
+                        Ok(())
                     }
                 } else {
-                    return Err(TasError::Syntax {
-                        l: line,
-                        c: col,
-                        e: "Operations cannot appear inside brackets",
-                        p: i.into(),
-                    });
+                    Err("Expected whitespace before operation.")
                 }
             }
-            _ => {}
+            // stray carriage returns are all that's left of windows line endings
+            '\r' if it.peek() == Some(&'\n') => Ok(()),
+            _ if src.strict => Err(UNEXPECTED),
+            _ => Ok(()),
+        };
+        if let Err(e) = res {
+            let mut err = src.syntax_err((line, start, col + 1), e);
+            if e == UNEXPECTED || e == LINE_START {
+                err = unexpected_help(src, err, chr, &it);
+            }
+            errs.push(err);
+            // throw away the broken line and carry on from the next one
+            out.truncate(line_start);
+            bracketed = false;
+            paren = false;
+            if chr == '\n' {
+                out.push(Token::Newline((line, col, col + 1)));
+                line += 1;
+                col = 0;
+                line_start = out.len();
+                continue;
+            }
+            while it.peek().is_some_and(|c| *c != '\n') {
+                it.next();
+                col += 1;
+            }
         }
-        if chr != '\n' {
-            col += 1;
+        col += 1;
+    }
+    (out, errs)
+}
+
+/// Add a "did you mean" note to `err` if `word` is a likely typo of one of `known`.
+pub(crate) fn suggest(err: TasError, word: &str, known: &[&str]) -> TasError {
+    let best = known
+        .iter()
+        .map(|k| (distance(word, k), k))
+        .min_by_key(|(d, _)| *d);
+    match best {
+        Some((d, k)) if d <= 2 && d < word.len() => err.with_help(format!("did you mean `{}`?", k)),
+        _ => err,
+    }
+}
+
+// levenshtein distance between two strings
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + (ca != *cb) as usize;
+            cur.push(sub.min(prev[j + 1] + 1).min(cur[j] + 1));
         }
+        prev = cur;
     }
-    Ok(out)
+    prev[b.len()]
 }
 
-pub fn gen_tas(infile: PathBuf) -> Result<Tas, TasError> {
-    PATH.set(infile.clone()).unwrap();
-    let prog = read_to_string(infile.clone()).map_err(|e| TasError::Fs {
-        e: format!("{}", e),
-    })?;
-    let tok = lex(prog)?;
-    Tas::parse_tas(tok)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FrameRate;
+
+    /// The frame and the keys pressed and released on every line of `text`.
+    fn lines(text: &str) -> Vec<(u64, Buttons, Buttons)> {
+        let tas: Tas = text.parse().unwrap();
+        tas.lines.iter().map(|l| (l.frame, l.on, l.off)).collect()
+    }
+
+    fn errors(text: &str) -> Vec<&'static str> {
+        let errs = text.parse::<Tas>().unwrap_err();
+        errs.iter()
+            .map(|e| match e {
+                TasError::Parse { e, .. } | TasError::Syntax { e, .. } => *e,
+                _ => panic!("unexpected error {:?}", e),
+            })
+            .collect()
+    }
+
+    const NONE: Buttons = Buttons::empty();
+
+    #[test]
+    fn repeat_plays_body_back_to_back() {
+        assert_eq!(
+            lines("10 REPEAT 3 {\n0 ON{KEY_A}\n2 OFF{KEY_A}\n}\n20 ON{KEY_B}"),
+            vec![
+                (10, Buttons::A, NONE),
+                (12, NONE, Buttons::A),
+                (13, Buttons::A, NONE),
+                (15, NONE, Buttons::A),
+                (16, Buttons::A, NONE),
+                (18, NONE, Buttons::A),
+                (20, Buttons::B, NONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_nest() {
+        let frames: Vec<u64> = lines("5 REPEAT 2 {\n0 REPEAT 2 {\n0 ON{KEY_A}\n1 OFF{KEY_A}\n}\n}")
+            .iter()
+            .map(|l| l.0)
+            .collect();
+        assert_eq!(frames, vec![5, 6, 7, 8, 9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn lines_after_a_block_come_after_all_of_it() {
+        assert_eq!(
+            errors("0 REPEAT 2 {\n1 ON{KEY_A}\n}\n3 OFF{KEY_A}"),
+            vec!["Frame numbers must strictly increase."]
+        );
+    }
+
+    #[test]
+    fn broken_blocks() {
+        assert_eq!(
+            errors("0 REPEAT 2 {\n}"),
+            vec!["This block has no lines in it."]
+        );
+        assert_eq!(
+            errors("0 REPEAT 2 {\n0 ON{KEY_A}"),
+            vec!["This block is never closed."]
+        );
+        assert_eq!(
+            errors("0 REPEAT 0 {\n0 ON{KEY_A}\n}"),
+            vec!["Repeat count must be a positive whole number."]
+        );
+        assert_eq!(
+            errors("18446744073709551614 REPEAT 2 {\n0 ON{KEY_A}\n}"),
+            vec!["This block runs for too many frames."]
+        );
+        assert_eq!(
+            errors("18446744073709551615 ON{KEY_A}"),
+            vec!["This frame number is too large."]
+        );
+        assert_eq!(
+            errors("0 REPEAT 4000000000 {\n0 ON{KEY_A}\n1 OFF{KEY_A}\n}"),
+            vec!["This block expands to too many lines."]
+        );
+        assert_eq!(
+            errors("0 REPEAT 100000000 {\n0 REPEAT 100000000 {\n0 ON{KEY_A}\n}\n}"),
+            vec!["This block expands to too many lines."]
+        );
+    }
+
+    #[test]
+    fn macros_substitute_arguments() {
+        let text =
+            "MACRO tap(k, len) {\n0 ON{$k}\n$len OFF{$k}\n}\n10 tap(KEY_A, 3)\n20 tap(KEY_B, 1)";
+        assert_eq!(
+            lines(text),
+            vec![
+                (10, Buttons::A, NONE),
+                (13, NONE, Buttons::A),
+                (20, Buttons::B, NONE),
+                (21, NONE, Buttons::B),
+            ]
+        );
+    }
+
+    #[test]
+    fn macros_substitute_stick_parameters() {
+        let tas: Tas = "MACRO tilt(a) {\n0 LSTICK{$a,32767}\n}\n4 tilt(90)"
+            .parse()
+            .unwrap();
+        assert_eq!(tas.lines[0].frame, 4);
+        assert_eq!(tas.lines[0].lstick, Some(Stick::from_polar(90.0, 32767.0)));
+    }
+
+    #[test]
+    fn lines_must_start_with_a_frame() {
+        let src = SourceFile::new("0 ON{KEY_A}".to_owned(), None);
+        let (toks, _) = lex(&src);
+        let err = Line::get(&toks[2..], &src).unwrap_err();
+        assert!(matches!(
+            err,
+            TasError::Parse {
+                e: "Expected a frame number to start the line.",
+                ..
+            }
+        ));
+        assert_eq!(errors("0 ON{5}"), vec!["Expected a key identifier."]);
+    }
+
+    #[test]
+    fn broken_macros() {
+        let tap = "MACRO tap(k) {\n0 ON{$k}\n1 OFF{$k}\n}\n";
+        assert_eq!(
+            errors(&format!("{}0 tap(KEY_A, KEY_B)", tap)),
+            vec!["Wrong number of arguments."]
+        );
+        assert_eq!(
+            errors(&format!("{}0 tapp(KEY_A)", tap)),
+            vec!["Unknown macro."]
+        );
+        assert_eq!(
+            errors(&format!("{}MACRO tap(k) {{\n0 ON{{$k}}\n}}", tap)),
+            vec!["A macro with this name already exists."]
+        );
+        assert_eq!(
+            errors("MACRO loop(k) {\n0 loop(KEY_A)\n}\n0 loop(KEY_A)"),
+            vec!["Macros cannot call themselves."]
+        );
+        assert_eq!(
+            errors("MACRO tap(k) {\n}"),
+            vec!["This macro has no lines in it."]
+        );
+        assert_eq!(
+            errors("0 ON{$k}"),
+            vec!["Parameters can only be used inside a macro."]
+        );
+        let tap2 = "MACRO tap(k, n) {\n0 ON{$k}\n$n OFF{$k}\n}\n";
+        assert_eq!(
+            errors(&format!("{}10 tap(KEY_A, KEY_B)", tap2)),
+            vec!["Expected a frame number for this argument."]
+        );
+        assert_eq!(
+            errors(&format!("{}20 tap(5, 3)", tap2)),
+            vec!["Expected a key for this argument."]
+        );
+    }
+
+    /// Write `files` into a fresh directory and parse the first of them.
+    fn parse_files(test: &str, files: &[(&str, &str)]) -> Result<Tas, Vec<TasError>> {
+        let dir = std::env::temp_dir().join(format!("tasc-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        let res = Tas::from_path(dir.join(files[0].0));
+        std::fs::remove_dir_all(&dir).unwrap();
+        res
+    }
+
+    #[test]
+    fn includes_are_offset() {
+        let tas = parse_files(
+            "offset",
+            &[
+                (
+                    "main.txt",
+                    "0 ON{KEY_A}\nINCLUDE \"part.txt\"\nINCLUDE \"part.txt\" at 20\n",
+                ),
+                ("part.txt", "0 ON{KEY_B}\n2 OFF{KEY_B}\n"),
+            ],
+        )
+        .unwrap();
+        let frames: Vec<u64> = tas.lines.iter().map(|l| l.frame).collect();
+        assert_eq!(frames, vec![0, 1, 3, 20, 22]);
+    }
+
+    #[test]
+    fn include_cycles_are_caught() {
+        let errs = parse_files(
+            "cycle",
+            &[
+                ("a.txt", "INCLUDE \"b.txt\"\n"),
+                ("b.txt", "0 ON{KEY_A}\nINCLUDE \"a.txt\"\n"),
+            ],
+        )
+        .unwrap_err();
+        match &errs[..] {
+            [TasError::Parse { e, p, l, .. }] => {
+                assert_eq!(*e, "This file is already being included.");
+                assert_eq!((p.file_name().unwrap(), *l), ("b.txt".as_ref(), 2));
+            }
+            e => panic!("unexpected errors {:?}", e),
+        }
+    }
+
+    #[test]
+    fn macro_errors_name_the_defining_file() {
+        let lib = "MACRO bad(k) {\n0 ON{$k}\n1 OFF{$k} LSTICK{0,40000}\n}\n\
+                   MACRO tap(k, n) {\n0 ON{$k}\n$n OFF{$k}\n}\n\
+                   MACRO tilt(m) {\n0 LSTICK{0,$m}\n}\n";
+        let main = "INCLUDE \"lib.txt\"\n10 bad(KEY_A)\n20 tap(KEY_A, 2.5)\n30 tilt(40000)\n";
+        let errs = parse_files("macro", &[("main.txt", main), ("lib.txt", lib)]).unwrap_err();
+        let found: Vec<(String, usize, usize, &str)> = errs
+            .iter()
+            .map(|e| match e {
+                TasError::Parse { p, l, c, src, .. } => (
+                    p.file_name().unwrap().to_string_lossy().into_owned(),
+                    *l,
+                    *c,
+                    src.as_str(),
+                ),
+                e => panic!("unexpected error {:?}", e),
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                // a bad argument is pointed out where it's given
+                ("main.txt".to_owned(), 3, 14, "20 tap(KEY_A, 2.5)"),
+                // and a bad body, or a value that only doesn't fit once it's in place, in the body
+                ("lib.txt".to_owned(), 3, 19, "1 OFF{$k} LSTICK{0,40000}"),
+                ("lib.txt".to_owned(), 10, 11, "0 LSTICK{0,$m}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_frames_count_from_the_previous_line() {
+        assert_eq!(
+            lines("+2 ON{KEY_A}\n+3 OFF{KEY_A}\n10 ON{KEY_B}\n+1 OFF{KEY_B}"),
+            vec![
+                (2, Buttons::A, NONE),
+                (5, NONE, Buttons::A),
+                (10, Buttons::B, NONE),
+                (11, NONE, Buttons::B),
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_frames_in_and_after_blocks() {
+        let frames: Vec<u64> =
+            lines("+\n10 REPEAT 2 {\n+1 ON{KEY_A}\n+1 OFF{KEY_A}\n}\n+3 ON{KEY_B}")
+                .iter()
+                .map(|l| l.0)
+                .collect();
+        assert_eq!(frames, vec![11, 12, 14, 15, 18]);
+    }
+
+    #[test]
+    fn broken_relative_frames() {
+        assert_eq!(
+            errors("0 ON{KEY_A}\n+0 OFF{KEY_A}"),
+            vec!["Frame numbers must strictly increase."]
+        );
+        assert_eq!(
+            errors("0 ON{KEY_A}\n+1.5 OFF{KEY_A}"),
+            vec!["Frame numbers must be whole numbers."]
+        );
+        assert_eq!(
+            errors("18446744073709551614 ON{KEY_A}\n+2 OFF{KEY_A}"),
+            vec!["This frame number is too large."]
+        );
+    }
+
+    #[test]
+    fn header_values_dont_look_like_nxtas() {
+        let tas: Tas = "TITLE Any%; glitchless\n1 ON{KEY_A}".parse().unwrap();
+        assert_eq!(tas.meta.title.as_deref(), Some("Any%; glitchless"));
+        let src = SourceFile::new("1 KEY_A 0;0 0;0\n".to_owned(), None);
+        assert_eq!(src.format(), Format::NxTas);
+    }
+
+    #[test]
+    fn header_fields() {
+        let tas: Tas =
+            "TITLE \"#1 route\"\nGAME_VERSION 1.3.0\nFRAMERATE 30\nCONTROLLER pro\n0 ON{KEY_A}"
+                .parse()
+                .unwrap();
+        assert_eq!(tas.meta.title.as_deref(), Some("#1 route"));
+        assert_eq!(tas.meta.game_version.as_deref(), Some("1.3.0"));
+        assert_eq!(tas.meta.framerate, FrameRate::new(30, 1).unwrap());
+        assert_eq!(
+            errors("TITLE a\nTITLE b\n0 ON{KEY_A}"),
+            vec!["This header field is already set."]
+        );
+    }
 }
@@ -0,0 +1,149 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+use crate::parse::{Line, Tas};
+use crate::sched::Scheduler;
+use crate::sink::{Gamepad, InputSink};
+use crate::{Buttons, FrameRate, TasError};
+
+impl Gamepad {
+    /// Apply the changes described by `line` on top of the current state.
+    pub fn update(&mut self, line: &Line) {
+        self.buttons = (self.buttons - line.off) | line.on;
+        if let Some(s) = line.lstick {
+            self.lx = s.x();
+            self.ly = s.y();
+        }
+        if let Some(s) = line.rstick {
+            self.rx = s.x();
+            self.ry = s.y();
+        }
+        self.lt = if self.buttons.contains(Buttons::ZL) {
+            255
+        } else {
+            0
+        };
+        self.rt = if self.buttons.contains(Buttons::ZR) {
+            255
+        } else {
+            0
+        };
+    }
+}
+
+impl Tas {
+    /// The controller state after each line, paired with the frame it takes effect on.
+    pub fn states(&self) -> Vec<(u64, Gamepad)> {
+        let mut pad = Gamepad::default();
+        self.lines
+            .iter()
+            .map(|line| {
+                pad.update(line);
+                (line.frame, pad)
+            })
+            .collect()
+    }
+
+    pub fn run_tas(&self, sink: &mut dyn InputSink, dbg: bool) -> Result<(), TasError> {
+        play(&self.states(), self.meta.framerate, sink, dbg)
+    }
+}
+
+/// Send every state to `sink` once its frame comes up.
+pub fn play(
+    states: &[(u64, Gamepad)],
+    framerate: FrameRate,
+    sink: &mut dyn InputSink,
+    dbg: bool,
+) -> Result<(), TasError> {
+    sink.connect()?;
+    let res = send(states, framerate, sink, dbg);
+    // unplug the controller even if playback failed, but report what made it fail
+    let done = sink.disconnect();
+    res.and(done)
+}
+
+fn send(
+    states: &[(u64, Gamepad)],
+    framerate: FrameRate,
+    sink: &mut dyn InputSink,
+    dbg: bool,
+) -> Result<(), TasError> {
+    let mut sched = Scheduler::new();
+    for (frame, pad) in states {
+        let at = framerate.at(*frame);
+        if dbg {
+            println!("Waiting for frame {} ({} ms).", frame, at.as_millis());
+        }
+        if sink.realtime() {
+            sched.wait_until(at)?;
+        }
+        if dbg {
+            println!(
+                "Buttons {:#b} on; {:#b} off",
+                pad.buttons.bits(),
+                !pad.buttons.bits()
+            );
+            println!(
+                "Lstick at ({}, {}); Rstick at ({}, {})",
+                pad.lx, pad.ly, pad.rx, pad.ry
+            );
+            println!("ZR: {}; ZL: {}", pad.rt >> 7, pad.lt >> 7);
+        }
+        sink.push(*frame, pad)?;
+    }
+    println!("Ran tas in {} ms", sched.elapsed().as_millis());
+    if sink.realtime() {
+        println!(
+            "Frame lateness: max {} us, mean {} us",
+            sched.max_late().as_micros(),
+            sched.mean_late().as_micros()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sink that remembers what happened to it, playing in real time.
+    #[derive(Default)]
+    struct Log {
+        pushed: Vec<u64>,
+        connected: bool,
+    }
+
+    impl InputSink for Log {
+        fn connect(&mut self) -> Result<(), TasError> {
+            self.connected = true;
+            Ok(())
+        }
+
+        fn push(&mut self, frame: u64, _pad: &Gamepad) -> Result<(), TasError> {
+            self.pushed.push(frame);
+            Ok(())
+        }
+
+        fn disconnect(&mut self) -> Result<(), TasError> {
+            self.connected = false;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frames_too_far_off_fail_and_unplug() {
+        let tas: Tas = "0 ON{KEY_A}\n2000000000000 OFF{KEY_A}".parse().unwrap();
+        let mut log = Log::default();
+        assert!(matches!(
+            tas.run_tas(&mut log, false),
+            Err(TasError::Backend { .. })
+        ));
+        assert_eq!(log.pushed, vec![0]);
+        assert!(!log.connected);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+use crate::sink::{Gamepad, InputSink};
+use crate::TasError;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Writes the controller state of every frame to a CSV file instead of a controller.
+///
+/// Frames between two pushes repeat the previous state, and when several pushes land on the
+/// same frame only the last one is kept, so the output has exactly one row per frame.
+pub struct Recorder {
+    path: PathBuf,
+    out: Option<BufWriter<File>>,
+    next: u64,
+    last: Gamepad,
+    pending: Option<(u64, Gamepad)>,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf) -> Self {
+        Recorder {
+            path,
+            out: None,
+            next: 0,
+            last: Gamepad::default(),
+            pending: None,
+        }
+    }
+
+    fn write_row(&mut self, frame: u64, pad: Gamepad) -> Result<(), TasError> {
+        let out = self.out.as_mut().ok_or(TasError::Backend {
+            e: "Recording has not been started.".into(),
+        })?;
+        writeln!(
+            out,
+            "{},{:#06x},{},{},{},{},{},{}",
+            frame,
+            pad.buttons.bits(),
+            pad.lx,
+            pad.ly,
+            pad.rx,
+            pad.ry,
+            pad.lt,
+            pad.rt
+        )
+        .map_err(fs_err)?;
+        self.next = frame + 1;
+        self.last = pad;
+        Ok(())
+    }
+
+    fn flush_pending(&mut self) -> Result<(), TasError> {
+        if let Some((frame, pad)) = self.pending.take() {
+            while self.next < frame {
+                self.write_row(self.next, self.last)?;
+            }
+            self.write_row(frame, pad)?;
+        }
+        Ok(())
+    }
+}
+
+fn fs_err(e: std::io::Error) -> TasError {
+    TasError::Fs {
+        e: format!("{}", e),
+    }
+}
+
+impl InputSink for Recorder {
+    fn connect(&mut self) -> Result<(), TasError> {
+        let mut out = BufWriter::new(File::create(&self.path).map_err(fs_err)?);
+        writeln!(out, "frame,buttons,lx,ly,rx,ry,lt,rt").map_err(fs_err)?;
+        self.out = Some(out);
+        self.next = 0;
+        self.last = Gamepad::default();
+        self.pending = None;
+        Ok(())
+    }
+
+    fn push(&mut self, frame: u64, pad: &Gamepad) -> Result<(), TasError> {
+        if !matches!(self.pending, Some((f, _)) if f == frame) {
+            self.flush_pending()?;
+        }
+        self.pending = Some((frame, *pad));
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> Result<(), TasError> {
+        self.flush_pending()?;
+        if let Some(mut out) = self.out.take() {
+            out.flush().map_err(fs_err)?;
+        }
+        println!("Recorded {} frames to {}", self.next, self.path.display());
+        Ok(())
+    }
+
+    fn realtime(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Buttons, Tas};
+
+    /// Run `record` against a fresh `Recorder` and return the rows it wrote, header included.
+    fn rows(test: &str, record: impl FnOnce(&mut Recorder)) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("tasc-{}-{}.csv", test, std::process::id()));
+        let mut rec = Recorder::new(path.clone());
+        record(&mut rec);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        text.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn gaps_get_a_row_per_frame() {
+        let tas: Tas = "1 ON{KEY_A}\n4 OFF{KEY_A}".parse().unwrap();
+        let rows = rows("gaps", |rec| tas.run_tas(rec, false).unwrap());
+        assert_eq!(
+            rows,
+            vec![
+                "frame,buttons,lx,ly,rx,ry,lt,rt",
+                "0,0x0000,0,0,0,0,0,0",
+                "1,0x1000,0,0,0,0,0,0",
+                "2,0x1000,0,0,0,0,0,0",
+                "3,0x1000,0,0,0,0,0,0",
+                "4,0x0000,0,0,0,0,0,0",
+            ]
+        );
+    }
+
+    #[test]
+    fn last_push_on_a_frame_wins() {
+        let a = Gamepad {
+            buttons: Buttons::A,
+            ..Gamepad::default()
+        };
+        let b = Gamepad {
+            buttons: Buttons::B,
+            ..Gamepad::default()
+        };
+        let rows = rows("same-frame", |rec| {
+            rec.connect().unwrap();
+            rec.push(0, &a).unwrap();
+            rec.push(0, &b).unwrap();
+            rec.push(2, &a).unwrap();
+            rec.disconnect().unwrap();
+        });
+        assert_eq!(
+            rows[1..],
+            [
+                "0,0x2000,0,0,0,0,0,0",
+                "1,0x2000,0,0,0,0,0,0",
+                "2,0x1000,0,0,0,0,0,0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+use std::time::{Duration, Instant};
+
+use crate::TasError;
+
+// how long before a deadline to stop sleeping and start spinning; os sleeps routinely overshoot
+// by a millisecond or more, so anything much shorter than this ends up late
+const SPIN: Duration = Duration::from_millis(2);
+
+/// Waits for deadlines that are all measured from the same starting instant,
+/// so oversleeping on one frame never pushes back the ones after it.
+pub struct Scheduler {
+    start: Instant,
+    waits: u32,
+    total_late: Duration,
+    max_late: Duration,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {
+            start: Instant::now(),
+            waits: 0,
+            total_late: Duration::ZERO,
+            max_late: Duration::ZERO,
+        }
+    }
+
+    /// Block until `offset` after the start, sleeping for most of the time and
+    /// spin-waiting for the rest. Fails without waiting if the clock can't reach that far.
+    pub fn wait_until(&mut self, offset: Duration) -> Result<(), TasError> {
+        let deadline = self
+            .start
+            .checked_add(offset)
+            .ok_or_else(|| TasError::Backend {
+                e: format!(
+                    "{} seconds after the start is further off than playback can wait.",
+                    offset.as_secs()
+                ),
+            })?;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            let left = deadline - now;
+            if left > SPIN {
+                std::thread::sleep(left - SPIN);
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+        let late = Instant::now().saturating_duration_since(deadline);
+        self.waits += 1;
+        self.total_late += late;
+        self.max_late = self.max_late.max(late);
+        Ok(())
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Worst lateness over all waits so far.
+    pub fn max_late(&self) -> Duration {
+        self.max_late
+    }
+
+    /// Average lateness over all waits so far.
+    pub fn mean_late(&self) -> Duration {
+        if self.waits == 0 {
+            Duration::ZERO
+        } else {
+            self.total_late / self.waits
+        }
+    }
+}
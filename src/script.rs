@@ -0,0 +1,268 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+//! Writing a [`Tas`] back out as canonical tas-script.
+//!
+//! Every frame line is written the same way: the frame number, then `ON`, `OFF`, `LSTICK` and
+//! `RSTICK` in that order, each left out when it would do nothing, separated by single spaces.
+//! Keys are listed in bit order (see [`Buttons`]) with no `NONE`s, and sticks are given as an
+//! angle and magnitude unless that can't hit their position exactly.
+
+use std::fmt::Write;
+
+use crate::parse::{lex, Num, Token};
+use crate::{Buttons, Format, Line, SourceFile, Stick, Tas, TasError};
+
+fn keys(b: Buttons) -> String {
+    if b.is_empty() {
+        return "NONE".to_owned();
+    }
+    let all = Buttons::all() - Buttons::TRIGGERS;
+    let mut names = vec![];
+    let mut rest = b;
+    if b.contains(all) {
+        names.push("ALL".to_owned());
+        rest -= all;
+    }
+    names.extend(rest.iter_names().map(|(n, _)| format!("KEY_{}", n)));
+    names.join(",")
+}
+
+/// A whole angle and magnitude close to the real ones that land exactly on `s`, if there are any.
+fn polar(s: Stick) -> Option<(u64, u64)> {
+    let ang = s.angle().round() as i64;
+    let mag = s.magnitude().round() as i64;
+    // closest to the rounded values first, so scripts keep the numbers they were written with
+    let mags: Vec<i64> = [mag, mag - 1, mag + 1, mag - 2, mag + 2]
+        .iter()
+        .copied()
+        .filter(|m| (0..=i16::MAX as i64).contains(m))
+        .collect();
+    let hits = |a: i64, m: i64| Stick::from_polar(a.rem_euclid(360) as f64, m as f64) == s;
+    for &m in &mags {
+        if let Some(a) = [ang, ang - 1, ang + 1].iter().find(|a| hits(**a, m)) {
+            return Some((a.rem_euclid(360) as u64, m as u64));
+        }
+    }
+    None
+}
+
+/// The canonical text of the code on one line of a script, if there is any. Lines in macros
+/// that use parameters can't be parsed on their own, so they're left as they are.
+fn code(l: &[Token], src: &SourceFile) -> Option<String> {
+    let mut toks = l
+        .iter()
+        .filter(|t| !matches!(t, Token::Comment(_, _) | Token::Whitespace(_)));
+    match toks.next()? {
+        // relative frames are kept relative
+        Token::Plus(_) => {
+            let rest = l.iter().position(|t| matches!(t, Token::Plus(_)))? + 1;
+            code(&l[rest..], src).map(|c| format!("+{}", c))
+        }
+        Token::Directive(d, _) => match toks.next() {
+            Some(Token::Value(v, _)) if !v.is_empty() => Some(format!("{} {}", d, v)),
+            _ => Some(d.clone()),
+        },
+        Token::BlockClose(_) => Some("}".to_owned()),
+        Token::Number(n, _) => match (toks.next(), toks.next()) {
+            (Some(Token::Repeat(_)), Some(Token::Number(Num::Int(count), _))) => {
+                Some(format!("{} REPEAT {} {{", n.frame().ok()?, count))
+            }
+            (Some(Token::Call(name, _)), Some(Token::BracketOpen(_))) => {
+                let args: Vec<String> = toks
+                    .filter_map(|t| match t {
+                        Token::Number(n, _) => Some(n.to_string()),
+                        Token::Key(k, _) => Some(k.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                Some(format!("{} {}({})", n.frame().ok()?, name, args.join(", ")))
+            }
+            _ => Line::get(l, src).ok().map(|l| line(&l)),
+        },
+        _ => None,
+    }
+}
+
+fn line(l: &Line) -> String {
+    let mut out = l.frame.to_string();
+    // only `RAW` can press keys it also releases, and it always releases everything
+    if l.on.intersects(l.off) {
+        write!(out, " RAW{{{}}}", keys(l.on)).unwrap();
+    } else {
+        if !l.on.is_empty() {
+            write!(out, " ON{{{}}}", keys(l.on)).unwrap();
+        }
+        if !l.off.is_empty() {
+            write!(out, " OFF{{{}}}", keys(l.off)).unwrap();
+        }
+    }
+    for (op, stick) in [("LSTICK", l.lstick), ("RSTICK", l.rstick)].iter() {
+        match stick.map(|s| (s, polar(s))) {
+            Some((_, Some((a, m)))) => write!(out, " {}{{{},{}}}", op, a, m).unwrap(),
+            Some((s, None)) => write!(out, " {}{{x={},y={}}}", op, s.x(), s.y()).unwrap(),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Write out `tas` as tas-script.
+pub(crate) fn write(tas: &Tas) -> String {
+    let mut out = String::new();
+    for d in tas.meta.directives() {
+        out.push_str(&d);
+        out.push('\n');
+    }
+    for l in tas {
+        out.push_str(&line(l));
+        out.push('\n');
+    }
+    out
+}
+
+/// Reformat the script in `src`, keeping its comments and header: the leading `+` and any
+/// directives.
+pub(crate) fn format(src: &SourceFile) -> Result<String, Vec<TasError>> {
+    if let Format::NxTas = src.format() {
+        return Err(TasError::Fs {
+            e: format!(
+                "{}: only tas-script can be formatted, use `convert` for nx-TAS",
+                src.name.display()
+            ),
+        }
+        .into());
+    }
+    let tas = src.parse()?;
+    let mut out = String::new();
+    // a lone `+` opening the script is kept, but `+N` starts an ordinary line
+    if src.text.lines().next().map(str::trim_end) == Some("+") {
+        out.push_str("+\n");
+    }
+    let (tokens, _) = lex(src);
+    for l in tokens.split(|t| matches!(t, Token::Newline(_))) {
+        // comments stay on the line they were on, on the same side of the code
+        let mut before = vec![];
+        let mut after = vec![];
+        let mut started = false;
+        for t in l {
+            match t {
+                Token::Comment(c, _) if !started => before.push(c.clone()),
+                Token::Comment(c, _) => after.push(c.clone()),
+                Token::Whitespace(_) => {}
+                _ => started = true,
+            }
+        }
+        if let Some(Token::Param(_, (n, _, _))) = l.iter().find(|t| matches!(t, Token::Param(_, _)))
+        {
+            out.push_str(src.line(*n).trim_end());
+            out.push('\n');
+            continue;
+        }
+        let code = code(l, src);
+        let parts: Vec<String> = before.into_iter().chain(code).chain(after).collect();
+        if !parts.is_empty() {
+            out.push_str(&parts.join(" "));
+            out.push('\n');
+        }
+    }
+    // the canonical form has to mean exactly what the original did
+    let again = SourceFile::new(out.clone(), Some(src.name.clone())).parse()?;
+    if again != tas {
+        return Err(TasError::Fs {
+            e: format!(
+                "{}: formatting would change the script, so it was left alone",
+                src.name.display()
+            ),
+        }
+        .into());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(text: &str) -> String {
+        format(&SourceFile::new(text.to_owned(), None)).unwrap()
+    }
+
+    fn parse(text: &str) -> Tas {
+        text.parse().unwrap()
+    }
+
+    const MESSY: &str = "\
+TITLE  Any%
+FRAMERATE 30 # half speed
+MACRO tap(k, f) {
+0 ON{$k}
+$f   OFF{$k}
+}
+// the run
+1   ON{KEY_B,KEY_A}  LSTICK{90,32767}
++4 OFF{KEY_B,KEY_A} /* let go */
+10 REPEAT 3 {
+0 ON{KEY_X}
++2 OFF{KEY_X}
+}
+20 tap(KEY_ZL,   5)
+30 RAW{KEY_Y} RSTICK{x=1200,y=-32767}
+";
+
+    #[test]
+    fn canonical_layout() {
+        assert_eq!(
+            fmt(MESSY),
+            "\
+TITLE Any%
+FRAMERATE 30 # half speed
+MACRO tap(k, f) {
+0 ON{$k}
+$f   OFF{$k}
+}
+// the run
+1 ON{KEY_A,KEY_B} LSTICK{90,32767}
++4 OFF{KEY_A,KEY_B} /* let go */
+10 REPEAT 3 {
+0 ON{KEY_X}
++2 OFF{KEY_X}
+}
+20 tap(KEY_ZL, 5)
+30 RAW{KEY_Y} RSTICK{x=1200,y=-32767}
+"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let once = fmt(MESSY);
+        assert_eq!(fmt(&once), once);
+    }
+
+    #[test]
+    fn formatting_keeps_meaning() {
+        assert_eq!(parse(&fmt(MESSY)), parse(MESSY));
+    }
+
+    #[test]
+    fn written_scripts_parse_back() {
+        let tas = parse(MESSY);
+        assert_eq!(parse(&write(&tas)), tas);
+    }
+
+    #[test]
+    fn nxtas_is_not_formatted() {
+        assert!(format(&SourceFile::new("1 KEY_A 0;0 0;0\n".to_owned(), None)).is_err());
+    }
+
+    #[test]
+    fn relative_first_line_gets_no_plus_line() {
+        assert_eq!(fmt("+5 ON{KEY_A}\n"), "+5 ON{KEY_A}\n");
+        assert_eq!(fmt("+\n5 ON{KEY_A}\n"), "+\n5 ON{KEY_A}\n");
+    }
+}
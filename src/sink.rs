@@ -0,0 +1,83 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::{Buttons, TasError};
+
+/// The full state of the virtual controller on a single frame.
+///
+/// `buttons` includes ZL and ZR; `lt` and `rt` hold the analog trigger values derived from them.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Gamepad {
+    pub buttons: Buttons,
+    pub lx: i16,
+    pub ly: i16,
+    pub rx: i16,
+    pub ry: i16,
+    pub lt: u8,
+    pub rt: u8,
+}
+
+/// The kind of controller a tas is laid out for.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Controller {
+    /// A switch pro controller, presented to the emulator as an xbox 360 pad.
+    #[default]
+    Pro = 0,
+}
+
+impl FromStr for Controller {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "PRO" => Ok(Controller::Pro),
+            _ => Err(format!("`{}` is not a controller type.", s)),
+        }
+    }
+}
+
+impl Display for Controller {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Controller::Pro => write!(f, "PRO"),
+        }
+    }
+}
+
+/// Somewhere to send controller states to during playback.
+pub trait InputSink {
+    /// Set up the output, e.g. plug in a virtual controller.
+    fn connect(&mut self) -> Result<(), TasError>;
+    /// Send the controller state for `frame`.
+    fn push(&mut self, frame: u64, pad: &Gamepad) -> Result<(), TasError>;
+    /// Tear down the output once playback is done.
+    fn disconnect(&mut self) -> Result<(), TasError>;
+    /// Whether playback has to wait in real time between frames for this sink.
+    fn realtime(&self) -> bool {
+        true
+    }
+}
+
+/// The sink used when no other output is requested.
+#[cfg(windows)]
+pub fn default_sink() -> Result<Box<dyn InputSink>, TasError> {
+    Ok(Box::new(crate::vigem::Vigem::new()))
+}
+
+#[cfg(target_os = "linux")]
+pub fn default_sink() -> Result<Box<dyn InputSink>, TasError> {
+    Ok(Box::new(crate::uinput::Uinput::new()))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn default_sink() -> Result<Box<dyn InputSink>, TasError> {
+    Err(TasError::Backend {
+        e: "No output backend is available on this platform.".into(),
+    })
+}
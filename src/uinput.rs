@@ -0,0 +1,202 @@
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
+
+use crate::sink::{Gamepad, InputSink};
+use crate::{Buttons, TasError};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+// from linux/uinput.h; the `_IOW` numbers assume the generic ioctl layout (x86, arm, riscv)
+const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+const UI_SET_EVBIT: libc::c_ulong = 0x4004_5564;
+const UI_SET_KEYBIT: libc::c_ulong = 0x4004_5565;
+const UI_SET_ABSBIT: libc::c_ulong = 0x4004_5567;
+
+// from linux/input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const BUS_USB: u16 = 0x03;
+
+const BTN_A: u16 = 0x130;
+const BTN_B: u16 = 0x131;
+const BTN_X: u16 = 0x133;
+const BTN_Y: u16 = 0x134;
+const BTN_TL: u16 = 0x136;
+const BTN_TR: u16 = 0x137;
+const BTN_SELECT: u16 = 0x13a;
+const BTN_START: u16 = 0x13b;
+const BTN_THUMBL: u16 = 0x13d;
+const BTN_THUMBR: u16 = 0x13e;
+
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_Z: u16 = 0x02;
+const ABS_RX: u16 = 0x03;
+const ABS_RY: u16 = 0x04;
+const ABS_RZ: u16 = 0x05;
+const ABS_HAT0X: u16 = 0x10;
+const ABS_HAT0Y: u16 = 0x11;
+
+// same layout the xpad driver uses for a wired 360 pad, so SDL picks up the right mapping
+const BUTTONS: [(Buttons, u16); 10] = [
+    (Buttons::A, BTN_A),
+    (Buttons::B, BTN_B),
+    (Buttons::X, BTN_X),
+    (Buttons::Y, BTN_Y),
+    (Buttons::L, BTN_TL),
+    (Buttons::R, BTN_TR),
+    (Buttons::MINUS, BTN_SELECT),
+    (Buttons::PLUS, BTN_START),
+    (Buttons::LSTICK, BTN_THUMBL),
+    (Buttons::RSTICK, BTN_THUMBR),
+];
+
+/// Virtual Xbox 360 style controller created through `/dev/uinput`.
+pub struct Uinput {
+    dev: Option<File>,
+    last: Gamepad,
+}
+
+impl Uinput {
+    pub fn new() -> Self {
+        Uinput {
+            dev: None,
+            last: Gamepad::default(),
+        }
+    }
+}
+
+fn uinput_err(e: std::io::Error) -> TasError {
+    TasError::Backend {
+        e: format!("uinput: {}", e),
+    }
+}
+
+fn ioctl(dev: &File, req: libc::c_ulong, arg: libc::c_int) -> Result<(), TasError> {
+    if unsafe { libc::ioctl(dev.as_raw_fd(), req as _, arg) } < 0 {
+        return Err(uinput_err(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn event(ty: u16, code: u16, value: i32) -> libc::input_event {
+    // the kernel fills in the timestamp itself
+    let mut ev: libc::input_event = unsafe { std::mem::zeroed() };
+    ev.type_ = ty;
+    ev.code = code;
+    ev.value = value;
+    ev
+}
+
+fn dpad(buttons: Buttons, neg: Buttons, pos: Buttons) -> i32 {
+    buttons.contains(pos) as i32 - buttons.contains(neg) as i32
+}
+
+impl InputSink for Uinput {
+    fn connect(&mut self) -> Result<(), TasError> {
+        println!("Creating uinput device...");
+        let dev = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")
+            .map_err(uinput_err)?;
+        ioctl(&dev, UI_SET_EVBIT, EV_KEY as _)?;
+        for (_, code) in BUTTONS.iter() {
+            ioctl(&dev, UI_SET_KEYBIT, *code as _)?;
+        }
+        ioctl(&dev, UI_SET_EVBIT, EV_ABS as _)?;
+        let mut setup: libc::uinput_user_dev = unsafe { std::mem::zeroed() };
+        for (i, b) in b"tasc virtual Xbox 360 controller".iter().enumerate() {
+            setup.name[i] = *b as libc::c_char;
+        }
+        setup.id = libc::input_id {
+            bustype: BUS_USB,
+            vendor: 0x045e,
+            product: 0x028e,
+            version: 0x0110,
+        };
+        let axes = [
+            (ABS_X, -32768, 32767),
+            (ABS_Y, -32768, 32767),
+            (ABS_RX, -32768, 32767),
+            (ABS_RY, -32768, 32767),
+            (ABS_Z, 0, 255),
+            (ABS_RZ, 0, 255),
+            (ABS_HAT0X, -1, 1),
+            (ABS_HAT0Y, -1, 1),
+        ];
+        for (code, min, max) in axes.iter() {
+            ioctl(&dev, UI_SET_ABSBIT, *code as _)?;
+            setup.absmin[*code as usize] = *min;
+            setup.absmax[*code as usize] = *max;
+        }
+        let raw = unsafe {
+            std::slice::from_raw_parts(
+                &setup as *const _ as *const u8,
+                std::mem::size_of::<libc::uinput_user_dev>(),
+            )
+        };
+        (&dev).write_all(raw).map_err(uinput_err)?;
+        ioctl(&dev, UI_DEV_CREATE, 0)?;
+        println!("Created!");
+        self.dev = Some(dev);
+        self.last = Gamepad::default();
+        Ok(())
+    }
+
+    fn push(&mut self, _frame: u64, pad: &Gamepad) -> Result<(), TasError> {
+        let dev = self.dev.as_ref().ok_or(TasError::Backend {
+            e: "uinput device has not been created.".into(),
+        })?;
+        let mut evs = vec![];
+        for (bit, code) in BUTTONS.iter() {
+            if (pad.buttons ^ self.last.buttons).intersects(*bit) {
+                evs.push(event(EV_KEY, *code, pad.buttons.contains(*bit) as i32));
+            }
+        }
+        // evdev has y pointing down, xinput has it pointing up
+        evs.push(event(EV_ABS, ABS_X, pad.lx as i32));
+        evs.push(event(EV_ABS, ABS_Y, pad.ly.saturating_neg() as i32));
+        evs.push(event(EV_ABS, ABS_RX, pad.rx as i32));
+        evs.push(event(EV_ABS, ABS_RY, pad.ry.saturating_neg() as i32));
+        evs.push(event(EV_ABS, ABS_Z, pad.lt as i32));
+        evs.push(event(EV_ABS, ABS_RZ, pad.rt as i32));
+        evs.push(event(
+            EV_ABS,
+            ABS_HAT0X,
+            dpad(pad.buttons, Buttons::DLEFT, Buttons::DRIGHT),
+        ));
+        evs.push(event(
+            EV_ABS,
+            ABS_HAT0Y,
+            dpad(pad.buttons, Buttons::DUP, Buttons::DDOWN),
+        ));
+        evs.push(event(EV_SYN, SYN_REPORT, 0));
+        let raw = unsafe {
+            std::slice::from_raw_parts(
+                evs.as_ptr() as *const u8,
+                evs.len() * std::mem::size_of::<libc::input_event>(),
+            )
+        };
+        let mut dev = dev;
+        dev.write_all(raw).map_err(uinput_err)?;
+        self.last = *pad;
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> Result<(), TasError> {
+        if let Some(dev) = self.dev.take() {
+            ioctl(&dev, UI_DEV_DESTROY, 0)?;
+        }
+        Ok(())
+    }
+}
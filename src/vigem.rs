@@ -1,71 +1,62 @@
-use crate::parse::Tas;
-use crate::TasError;
-use std::time::Instant;
-use vigem_client::{Client, TargetId, XButtons, Xbox360Wired};
+/*
+ * Copyright 2021 LtPeriwinkle
+ *
+ * Licensed under GPLv3 or later.
+ * Refer to included LICENSE file.
+ */
 
-impl Tas {
-    pub fn run_tas(&mut self, dbg: bool) -> Result<(), TasError> {
+use crate::sink::{Gamepad, InputSink};
+use crate::{Buttons, TasError};
+use vigem_client::{Client, TargetId, XButtons, XGamepad, Xbox360Wired};
+
+/// Virtual Xbox 360 controller provided by ViGEmBus.
+pub struct Vigem {
+    target: Option<Xbox360Wired<Client>>,
+}
+
+impl Vigem {
+    pub fn new() -> Self {
+        Vigem { target: None }
+    }
+}
+
+fn vigem_err(e: vigem_client::Error) -> TasError {
+    TasError::Vigem { e: e.to_string() }
+}
+
+impl InputSink for Vigem {
+    fn connect(&mut self) -> Result<(), TasError> {
         println!("Connecting to ViGEm...");
-        let client = Client::connect().map_err(|e| TasError::Vigem { e: e.to_string() })?;
+        let client = Client::connect().map_err(vigem_err)?;
         let mut target = Xbox360Wired::new(client, TargetId::XBOX360_WIRED);
-        target
-            .plugin()
-            .map_err(|e| TasError::Vigem { e: e.to_string() })?;
+        target.plugin().map_err(vigem_err)?;
+        target.wait_ready().map_err(vigem_err)?;
         println!("Connected!");
-        let start = Instant::now();
-        let mut on: u16 = 0;
-        let mut gamepad = vigem_client::XGamepad::default();
-        let (mut lx, mut ly) = (0, 0);
-        let (mut rx, mut ry) = (0, 0);
-        let (mut lt, mut rt) = (0, 0);
-        for line in &self.lines {
-            if dbg {
-                println!("Sleeping for {} frames.", line.delay.as_nanos() / 16666666);
-            }
-            std::thread::sleep(line.delay);
-            let on_without_triggers = !(line.on & 0xC00); // mask the bits for triggers, 0x400 and 0x800
-            let on_without_off = on_without_triggers & !line.off; // turn off bits for things that are not meant to be on
-            on |= on_without_off;
-            gamepad.buttons = XButtons::from(on);
-            if dbg {
-                println!("Buttons {:#b} on; {:#b} off", on, !on);
-            }
-            if let Some(s) = line.lstick {
-                lx = s.x;
-                ly = s.y;
-            }
-            gamepad.thumb_lx = lx;
-            gamepad.thumb_ly = ly;
-            if let Some(s) = line.rstick {
-                rx = s.x;
-                ry = s.y;
-            }
-            gamepad.thumb_rx = rx;
-            gamepad.thumb_ry = ry;
-            if dbg {
-                println!("Lstick at ({}, {}); Rstick at ({}, {})", lx, ly, rx, ry);
-            }
-            let triggers = on >> 10;
-            if triggers & 1 == 0 {
-                lt = 255;
-            }
-            if (triggers >> 1) & 1 == 0 {
-                rt = 255;
-            }
-            let t_off = line.off >> 10;
-            if t_off & 1 == 0 {
-                lt = 0;
-            }
-            if (t_off >> 1) & 1 == 0 {
-                rt = 0;
-            }
-            gamepad.left_trigger = lt;
-            gamepad.right_trigger = rt;
-            if dbg {
-                println!("ZR: {}; ZL: {}", rt >> 7, lt >> 7);
-            }
+        self.target = Some(target);
+        Ok(())
+    }
+
+    fn push(&mut self, _frame: u64, pad: &Gamepad) -> Result<(), TasError> {
+        let target = self.target.as_mut().ok_or(TasError::Vigem {
+            e: "Controller is not plugged in.".into(),
+        })?;
+        let gamepad = XGamepad {
+            // ZL and ZR live where GUIDE would be, so keep them out of the buttons
+            buttons: XButtons::from((pad.buttons - Buttons::TRIGGERS).bits()),
+            left_trigger: pad.lt,
+            right_trigger: pad.rt,
+            thumb_lx: pad.lx,
+            thumb_ly: pad.ly,
+            thumb_rx: pad.rx,
+            thumb_ry: pad.ry,
+        };
+        target.update(&gamepad).map_err(vigem_err)
+    }
+
+    fn disconnect(&mut self) -> Result<(), TasError> {
+        if let Some(mut target) = self.target.take() {
+            target.unplug().map_err(vigem_err)?;
         }
-        println!("Ran tas in {} ms", start.elapsed().as_millis());
         Ok(())
     }
 }